@@ -1,5 +1,4 @@
 // extern crate lalrpop;
-use lalrpop;
 
 fn main() {
     lalrpop::process_root().unwrap();
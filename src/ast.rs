@@ -1,34 +1,237 @@
 use std::fmt;
+use crate::span::Span;
+use crate::errors::Diagnostic;
 
+//A syntax tree node together with the part of the source it was parsed from
 #[derive(Debug, Clone)]
-pub enum Node {
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(kind: NodeKind, span: Span) -> Node {
+        Node {kind, span}
+    }
+
+    //Collects the syntax errors the parser recovered from, in source order
+    pub fn syntax_errors(&self, errors: &mut Vec<Diagnostic>) {
+        let mut children: Vec<&Node> = Vec::new();
+        match &self.kind {
+            NodeKind::Error(d) => errors.push((**d).clone()),
+            NodeKind::Program(v) => children.extend(v.iter().map(|n| &**n)),
+            NodeKind::Op(a, _, b) | NodeKind::Range(a, b, _, _) | NodeKind::Index(a, b) => children.extend([&**a, &**b]),
+            NodeKind::For(_, _, r, v) => {
+                children.push(r);
+                children.extend(v.iter().map(|n| &**n));
+            }
+            NodeKind::UnaryOp(_, n) | NodeKind::Cast(n, _) | NodeKind::Assign(_, n) | NodeKind::BlockValue(n) | NodeKind::WriteByRef(_, _, n) => children.push(n),
+            NodeKind::Call(_, v) | NodeKind::Print(_, _, v) => children.extend(v.iter().map(|n| &**n)),
+            NodeKind::MethodCall(n, _, v) => {
+                children.push(n);
+                children.extend(v.iter().map(|n| &**n));
+            }
+            NodeKind::IfStmt(c, v) | NodeKind::While(_, c, v) => {
+                children.push(c);
+                children.extend(v.iter().map(|n| &**n));
+            }
+            NodeKind::IfElse(c, v1, v2) => {
+                children.push(c);
+                children.extend(v1.iter().chain(v2.iter()).map(|n| &**n));
+            }
+            NodeKind::FnDef(_, p, _, v, _) => children.extend(p.iter().chain(v.iter()).map(|n| &**n)),
+            NodeKind::Loop(_, v) | NodeKind::Block(v) => children.extend(v.iter().map(|n| &**n)),
+            NodeKind::Declaration(_, _, _, Some(n)) | NodeKind::Return(Some(n)) | NodeKind::Break(_, Some(n)) => children.push(n),
+            _ => (),
+        }
+        for c in children {
+            c.syntax_errors(errors);
+        }
+    }
+}
+
+//Used by the grammar actions, lo and hi being the @L and @R locations
+pub fn spanned(lo: usize, kind: NodeKind, hi: usize) -> Box<Node> {
+    Box::new(Node::new(kind, Span::new(lo, hi)))
+}
+
+//Attaches the doc comment written before a function to it
+pub fn with_doc(mut node: Box<Node>, doc: Option<String>) -> Box<Node> {
+    if let NodeKind::FnDef(_, _, _, _, d) = &mut node.kind {
+        *d = doc;
+    }
+    node
+}
+
+//Placeholder for code skipped by the parser's error recovery
+pub fn error_node(diagnostic: Diagnostic) -> Box<Node> {
+    let span = diagnostic.span.unwrap_or_default();
+    Box::new(Node::new(NodeKind::Error(Box::new(diagnostic)), span))
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeKind {
     Program(Vec<Box<Node>>),
-    Number(i32),
+    //Integer literal with its suffix, negative numbers are negated literals
+    Number(u64, Option<IntType>),
+    Float(f64),
+    //String literal with its escapes resolved
+    Str(String),
+    Char(char),
     ID(String),
-    //String describes the type
-    Type(String),
+    //A type written in the program
+    Type(TypeExpr),
     //+, -, *, /, %
     Op(Box<Node>, Opcode, Box<Node>),
     //unary op
     UnaryOp(Opcode, Box<Node>),
     //Function call
     Call(String, Vec<Box<Node>>),
+    //Method called on a value, like `s.len()`
+    MethodCall(Box<Node>, String, Vec<Box<Node>>),
+    //Indexing a value, like `s[0]`
+    Index(Box<Node>, Box<Node>),
+    //`print!` or `println!` when the bool is set, with the Str node of the format string
+    Print(bool, Box<Node>, Vec<Box<Node>>),
     //Value assigned to a variable
     Assign(String, Box<Node>),
     IfStmt(Box<Node>, Vec<Box<Node>>),
     IfElse(Box<Node>, Vec<Box<Node>>, Vec<Box<Node>>),
-    While(Box<Node>, Vec<Box<Node>>),
-    //Function definition
-    FnDef(String, Vec<Box<Node>>, Option<String>, Vec<Box<Node>>),
-    //Definition of parameters in function definition
-    ParamDef(String, String),
-    //Variable declaration with optional type spec and value
-    Declaration(String, bool, Option<String>, Option<Box<Node>>),
+    //A block with its own scope, its value is that of its trailing expression
+    Block(Vec<Box<Node>>),
+    //Loops can have a label, stored without the leading `'`
+    While(Option<String>, Box<Node>, Vec<Box<Node>>),
+    Loop(Option<String>, Vec<Box<Node>>),
+    //Loop over a range with an immutable loop variable
+    For(Option<String>, String, Box<Node>, Vec<Box<Node>>),
+    //Conversion of a value to the type of the Type node with `as`
+    Cast(Box<Node>, Box<Node>),
+    //Start and end of a range, whether the end is included and whether it is iterated in reverse
+    Range(Box<Node>, Box<Node>, bool, bool),
+    //Break out of the innermost or labelled loop, a `loop` can be given a value
+    Break(Option<String>, Option<Box<Node>>),
+    Continue(Option<String>),
+    //Function definition, with the text of its doc comment, the return type is a Type node
+    FnDef(String, Vec<Box<Node>>, Option<Box<Node>>, Vec<Box<Node>>, Option<String>),
+    //Definition of parameters in function definition, with a Type node
+    ParamDef(String, Box<Node>),
+    //Variable declaration with optional Type node and value
+    Declaration(String, bool, Option<Box<Node>>, Option<Box<Node>>),
     //Return
     Return(Option<Box<Node>>),
     Boolean(bool),
+    //The unit value `()`
+    Unit,
     BlockValue(Box<Node>),
     WriteByRef(Opcode, String, Box<Node>),
+    //Code the parser could not make sense of and skipped
+    Error(Box<Diagnostic>),
+}
+
+//The integer types, usize being 64 bits wide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+}
+
+impl IntType {
+    pub fn from_name(name: &str) -> Option<IntType> {
+        match name {
+            "i8" => Some(IntType::I8),
+            "i16" => Some(IntType::I16),
+            "i32" => Some(IntType::I32),
+            "i64" => Some(IntType::I64),
+            "u8" => Some(IntType::U8),
+            "u16" => Some(IntType::U16),
+            "u32" => Some(IntType::U32),
+            "u64" => Some(IntType::U64),
+            "usize" => Some(IntType::Usize),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntType::I8 => "i8",
+            IntType::I16 => "i16",
+            IntType::I32 => "i32",
+            IntType::I64 => "i64",
+            IntType::U8 => "u8",
+            IntType::U16 => "u16",
+            IntType::U32 => "u32",
+            IntType::U64 => "u64",
+            IntType::Usize => "usize",
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            IntType::I8 | IntType::U8 => 8,
+            IntType::I16 | IntType::U16 => 16,
+            IntType::I32 | IntType::U32 => 32,
+            IntType::I64 | IntType::U64 | IntType::Usize => 64,
+        }
+    }
+
+    pub fn signed(&self) -> bool {
+        matches!(self, IntType::I8 | IntType::I16 | IntType::I32 | IntType::I64)
+    }
+
+    pub fn min(&self) -> i128 {
+        if self.signed() {-(1 << (self.bits() - 1))} else {0}
+    }
+
+    pub fn max(&self) -> i128 {
+        if self.signed() {(1 << (self.bits() - 1)) - 1} else {(1 << self.bits()) - 1}
+    }
+
+    pub fn contains(&self, value: i128) -> bool {
+        self.min() <= value && value <= self.max()
+    }
+
+    //Keeps the low bits of a value and sign-extends them for signed types, like `as` does
+    pub fn wrap(&self, value: i128) -> i128 {
+        let bits = self.bits();
+        let low = value & ((1 << bits) - 1);
+        if self.signed() && low > self.max() {
+            return low - (1 << bits);
+        }
+        low
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+//The syntax of a type, names are resolved by the type checker
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeExpr {
+    Named(String),
+    Unit,
+    Ref(Box<TypeExpr>),
+    MutRef(Box<TypeExpr>),
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeExpr::Named(name) => write!(f, "{}", name),
+            TypeExpr::Unit => write!(f, "()"),
+            TypeExpr::Ref(t) => write!(f, "&{}", t),
+            TypeExpr::MutRef(t) => write!(f, "&mut {}", t),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -67,7 +270,13 @@ impl fmt::Display for Opcode {
             Opcode::GreaterorEq => write!(f, ">="),
             Opcode::Equals => write!(f, "=="),
             Opcode::Neq => write!(f, "!="),
-            _ => panic!("error"),
+            Opcode::And => write!(f, "&&"),
+            Opcode::Or => write!(f, "||"),
+            Opcode::Not => write!(f, "!"),
+            Opcode::UnarySub => write!(f, "-"),
+            Opcode::Ref => write!(f, "&"),
+            Opcode::DeRef => write!(f, "*"),
+            Opcode::MutRef => write!(f, "&mut"),
         }?;
         Ok(())
     }
@@ -76,11 +285,15 @@ impl fmt::Display for Opcode {
 
 impl fmt::Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Node::Number(i) => write!(f, "{}", i)?,
-            Node::ID(s) => write!(f, "{}", s)?,
-            Node::Op(a, b, c) => write!(f, "({} {} {})", format!("{}", a), format!("{}", b), format!("{}", c))?,
-            Node::Assign(a, b) => write!(f, "let {} = {};", format!("{}", a), format!("{}", b))?,
+        match &self.kind {
+            NodeKind::Number(i, None) => write!(f, "{}", i)?,
+            NodeKind::Number(i, Some(t)) => write!(f, "{}{}", i, t)?,
+            NodeKind::Float(x) => write!(f, "{:?}", x)?,
+            NodeKind::Str(s) => write!(f, "{:?}", s)?,
+            NodeKind::Char(c) => write!(f, "{:?}", c)?,
+            NodeKind::ID(s) => write!(f, "{}", s)?,
+            NodeKind::Op(a, b, c) => write!(f, "({} {} {})", a, b, c)?,
+            NodeKind::Assign(a, b) => write!(f, "let {} = {};", a, b)?,
             //Expr::Call(s, v, o) => write!(f, "{}({})", format!("{}", s), format!("{}", v))?,
             //Expr::Call(s, v, o) => write!(f, "ok"),
             _ => panic!("error"),
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::ast::IntType;
+use crate::errors::{Diagnostic, ErrorCode, RuntimeError};
+use crate::interpreter::{OverflowMode, Value};
+use crate::typechecker::Types;
+
+//The Rust side of a builtin, called with the evaluated arguments and the overflow mode the
+//program runs with. Errors without a span are reported at the call.
+pub type NativeFn = Rc<dyn Fn(&[Value], OverflowMode) -> Result<Value, RuntimeError>>;
+
+//A function implemented in Rust, with the signature the type checker uses for calls to it.
+//Parameters of type Unknown accept any type, as long as all of them get the same one.
+#[derive(Clone)]
+pub struct Builtin {
+    pub params: Vec<Types>,
+    pub ret: Types,
+    pub run: NativeFn,
+}
+
+//The builtin functions by name, a function defined by the program hides a builtin with its name
+#[derive(Clone, Default)]
+pub struct Builtins {
+    fns: HashMap<String, Builtin>,
+}
+
+impl Builtins {
+    pub fn get(&self, name: &str) -> Option<&Builtin> {
+        self.fns.get(name)
+    }
+
+    //Registers a function, replacing any builtin with the same name. Embedding applications use
+    //this to let programs call into them, the interpreter checks that `run` returns a value of
+    //type `ret`.
+    pub fn insert<F>(&mut self, name: &str, params: Vec<Types>, ret: Types, run: F)
+    where F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static {
+        self.insert_with_mode(name, params, ret, move |args, _| run(args));
+    }
+
+    //Like `insert`, for functions whose arithmetic follows the overflow mode of the program
+    pub fn insert_with_mode<F>(&mut self, name: &str, params: Vec<Types>, ret: Types, run: F)
+    where F: Fn(&[Value], OverflowMode) -> Result<Value, RuntimeError> + 'static {
+        self.fns.insert(name.to_string(), Builtin {params, ret, run: Rc::new(run)});
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Builtin)> {
+        self.fns.iter()
+    }
+}
+
+const I32: Types = Types::Int(IntType::I32);
+
+//The builtins every program can call
+pub fn prelude() -> Builtins {
+    let mut b = Builtins::default();
+    b.insert_with_mode("abs", vec![I32], I32, |args, mode| {
+        let x = int(&args[0]);
+        match x.checked_abs() {
+            Some(v) => Ok(Value::Int(v as i128, Some(IntType::I32))),
+            None if mode == OverflowMode::Wrap => Ok(Value::Int(x.wrapping_abs() as i128, Some(IntType::I32))),
+            None => Err(Diagnostic::unlocated(ErrorCode::IntegerOverflow, &format!("attempt to negate `{}` with overflow", x))),
+        }
+    });
+    b.insert("min", vec![I32, I32], I32, |args| Ok(Value::Int(int(&args[0]).min(int(&args[1])) as i128, Some(IntType::I32))));
+    b.insert("max", vec![I32, I32], I32, |args| Ok(Value::Int(int(&args[0]).max(int(&args[1])) as i128, Some(IntType::I32))));
+    b.insert_with_mode("pow", vec![I32, Types::Int(IntType::U32)], I32, |args, mode| {
+        let (base, exp) = (int(&args[0]), int(&args[1]));
+        match base.checked_pow(exp as u32) {
+            Some(v) => Ok(Value::Int(v as i128, Some(IntType::I32))),
+            None if mode == OverflowMode::Wrap => Ok(Value::Int(base.wrapping_pow(exp as u32) as i128, Some(IntType::I32))),
+            None => Err(Diagnostic::unlocated(ErrorCode::IntegerOverflow, &format!("attempt to calculate `pow({}, {})` with overflow", base, exp))),
+        }
+    });
+    b.insert("assert", vec![Types::Boolean], Types::UnitType, |args| match args[0] {
+        Value::Boolean(true) => Ok(Value::Unit),
+        _ => Err(Diagnostic::unlocated(ErrorCode::Panic, "assertion failed")),
+    });
+    b.insert("assert_eq", vec![Types::Unknown, Types::Unknown], Types::UnitType, |args| {
+        if args[0].equals(&args[1]) {
+            return Ok(Value::Unit);
+        }
+        Err(Diagnostic::unlocated(ErrorCode::Panic, "assertion `left == right` failed")
+            .with_note(&format!("left: {}, right: {}", args[0].debug(), args[1].debug())))
+    });
+    b.insert("panic", vec![Types::Str], Types::UnitType, |args| {
+        Err(Diagnostic::unlocated(ErrorCode::Panic, &format!("panicked: {}", args[0])))
+    });
+    b
+}
+
+//The type checker made sure the argument is an i32
+fn int(value: &Value) -> i32 {
+    match value {
+        Value::Int(n, _) => *n as i32,
+        _ => 0,
+    }
+}
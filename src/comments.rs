@@ -0,0 +1,174 @@
+use crate::errors::{Diagnostic, ErrorCode};
+use crate::span::Span;
+
+//Blanks out `//` line comments and nested `/* */` block comments before the source is lexed.
+//Every byte of a comment except newlines becomes a space, so spans in the blanked source
+//still point at the original text. `///` doc comments are kept for the parser to attach to
+//the function following them. Inside a function they are ordinary comments, like rustc only
+//warns about them there, unless they end a block without anything to document. String and char
+//literals are skipped, so `"//"` is not a comment.
+pub fn strip_comments(src: &str) -> Result<String, Diagnostic> {
+    let bytes = src.as_bytes();
+    let mut out = bytes.to_vec();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"//") {
+            let end = match bytes[i..].iter().position(|b| *b == b'\n') {
+                Some(n) => i + n,
+                None => bytes.len(),
+            };
+            //`////` and longer are ordinary comments, like in Rust
+            let doc = bytes[i..end].starts_with(b"///") && !bytes[i..end].starts_with(b"////");
+            if !doc || (depth > 0 && !ends_block(bytes, end)) {
+                blank(&mut out, i, end);
+            }
+            i = end;
+        }
+        else if bytes[i..].starts_with(b"/*") {
+            let end = block_comment_end(bytes, i)?;
+            blank(&mut out, i, end);
+            i = end;
+        }
+        else if bytes[i] == b'"' {
+            i = string_end(bytes, i);
+        }
+        else if bytes[i] == b'\'' {
+            i = char_end(src, i);
+        }
+        else {
+            match bytes[i] {
+                b'{' => depth += 1,
+                b'}' => depth -= 1,
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+    //Only whole comments, which always start and end on ASCII characters, have been replaced
+    Ok(String::from_utf8(out).unwrap_or_default())
+}
+
+//Whether only whitespace and line comments come between `start` and the `}` closing a block
+fn ends_block(bytes: &[u8], start: usize) -> bool {
+    let mut i = start;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        }
+        else if bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        else {
+            return bytes[i] == b'}';
+        }
+    }
+    true
+}
+
+//The offset just after the `*/` closing the block comment starting at `start`
+fn block_comment_end(bytes: &[u8], start: usize) -> Result<usize, Diagnostic> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        }
+        else if bytes[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return Ok(i);
+            }
+        }
+        else {
+            i += 1;
+        }
+    }
+    Err(Diagnostic::error(ErrorCode::UnterminatedComment, "unterminated block comment", Span::new(start, start + 2))
+        .with_note("block comments nest, every `/*` needs its own `*/`"))
+}
+
+//The offset just after the string literal starting at `start`, an unterminated one is left to the lexer
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+//The offset just after the char literal starting at `start`, or just after the `'` when it
+//starts a loop label instead
+fn char_end(src: &str, start: usize) -> usize {
+    let rest = &src[start + 1..];
+    if rest.starts_with('\\') {
+        //An escape, which never contains a `'` after its first character
+        return match rest.get(2..).and_then(|r| r.find('\'')) {
+            Some(n) => start + 3 + n + 1,
+            None => start + 1,
+        };
+    }
+    match rest.chars().next() {
+        Some(c) if rest[c.len_utf8()..].starts_with('\'') => start + 1 + c.len_utf8() + 1,
+        _ => start + 1,
+    }
+}
+
+//The innermost bracket still open at offset `at`, skipping literals and the doc comments left in
+//the source
+pub fn open_delimiter(src: &str, at: usize) -> Option<u8> {
+    let bytes = src.as_bytes();
+    let mut open = Vec::new();
+    let mut i = 0;
+    while i < at {
+        match bytes[i] {
+            b'/' if bytes[i..].starts_with(b"//") => {
+                i = match bytes[i..].iter().position(|b| *b == b'\n') {
+                    Some(n) => i + n,
+                    None => bytes.len(),
+                };
+                continue;
+            },
+            b'"' => {
+                i = string_end(bytes, i);
+                continue;
+            },
+            b'\'' => {
+                i = char_end(src, i);
+                continue;
+            },
+            b'(' | b'[' | b'{' => open.push(bytes[i]),
+            b')' | b']' | b'}' => {
+                open.pop();
+            },
+            _ => (),
+        }
+        i += 1;
+    }
+    open.pop()
+}
+
+fn blank(out: &mut [u8], lo: usize, hi: usize) {
+    for b in &mut out[lo..hi] {
+        if *b != b'\n' {
+            *b = b' ';
+        }
+    }
+}
+
+//The text of the `///` lines before a function, without the slashes and the space following them
+pub fn doc_text(lines: &[&str]) -> String {
+    let text: Vec<&str> = lines.iter().map(|l| {
+        let l = &l[3..];
+        l.strip_prefix(' ').unwrap_or(l).trim_end()
+    }).collect();
+    text.join("\n")
+}
@@ -0,0 +1,244 @@
+use std::cmp;
+use std::env;
+use std::io::{self, IsTerminal};
+use crate::errors::{Diagnostic, Severity};
+use crate::span::{Span, SourceFile};
+use crate::typechecker::Types;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Option<ColorChoice> {
+        match s {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    //Auto only colors output written to a terminal, and respects NO_COLOR
+    pub fn use_color(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn parse(s: &str) -> Option<ErrorFormat> {
+        match s {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+//A span to underline, the primary one marked with `^` and secondary ones with `-`
+struct Annotation {
+    span: Span,
+    label: String,
+    primary: bool,
+}
+
+//Renders diagnostics in the style of rustc, with the source lines they point at,
+//or as one JSON object per diagnostic for other tools to read
+pub struct Emitter {
+    color: bool,
+    format: ErrorFormat,
+}
+
+impl Emitter {
+    pub fn new(choice: ColorChoice) -> Emitter {
+        Emitter {color: choice.use_color(), format: ErrorFormat::Human}
+    }
+
+    //Output without any escape codes, used by the tests
+    pub fn plain() -> Emitter {
+        Emitter {color: false, format: ErrorFormat::Human}
+    }
+
+    pub fn json() -> Emitter {
+        Emitter {color: false, format: ErrorFormat::Json}
+    }
+
+    pub fn render(&self, file: &SourceFile, diagnostic: &Diagnostic) -> String {
+        match self.format {
+            ErrorFormat::Human => self.render_human(file, diagnostic),
+            ErrorFormat::Json => render_json(file, diagnostic),
+        }
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            return format!("{}{}{}", style, text, RESET);
+        }
+        text.to_string()
+    }
+
+    fn severity_style(severity: Severity) -> &'static str {
+        match severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => GREEN,
+        }
+    }
+
+    fn render_human(&self, file: &SourceFile, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let header = format!("{}[{}]", diagnostic.severity, diagnostic.code);
+        out.push_str(&self.paint(Emitter::severity_style(diagnostic.severity), &header));
+        out.push_str(&self.paint(BOLD, &format!(": {}", diagnostic.message)));
+        out.push('\n');
+
+        let mut annotations = Vec::new();
+        if let Some(span) = diagnostic.span {
+            let label = match (&diagnostic.expected, &diagnostic.found) {
+                (Some(expected), Some(found)) => format!("expected `{}`, found `{}`", expected, found),
+                _ => String::new(),
+            };
+            annotations.push(Annotation {span, label, primary: true});
+        }
+        for (span, label) in &diagnostic.labels {
+            annotations.push(Annotation {span: *span, label: label.clone(), primary: false});
+        }
+
+        //The gutter is as wide as the largest line number shown
+        let mut lines: Vec<usize> = annotations.iter().map(|a| file.line_col(a.span.lo).line).collect();
+        lines.sort();
+        lines.dedup();
+        let width = match lines.last() {
+            Some(l) => l.to_string().len(),
+            None => 0,
+        };
+        let gutter = |text: &str| self.paint(BLUE, &format!("{:>width$} |", text, width = width));
+
+        if let Some(span) = diagnostic.span {
+            let pos = file.line_col(span.lo);
+            out.push_str(&format!("{}{} {}:{}:{}\n", " ".repeat(width), self.paint(BLUE, "-->"), file.name, pos.line, pos.col));
+            out.push_str(&gutter(""));
+            out.push('\n');
+        }
+
+        let mut previous: Option<usize> = None;
+        for line in &lines {
+            if let Some(p) = previous {
+                if *line > p + 1 {
+                    out.push_str(&self.paint(BLUE, "..."));
+                    out.push('\n');
+                }
+            }
+            previous = Some(*line);
+            let text = file.line(*line);
+            out.push_str(&format!("{} {}\n", gutter(&line.to_string()), text.replace('\t', TAB)));
+
+            let mut on_line: Vec<&Annotation> = annotations.iter().filter(|a| file.line_col(a.span.lo).line == *line).collect();
+            on_line.sort_by_key(|a| (!a.primary, a.span.lo));
+            for a in on_line {
+                let start = file.line_col(a.span.lo);
+                //A span continuing past this line is underlined up to its end
+                let end_col = if file.line_col(a.span.hi).line == *line {
+                    file.line_col(a.span.hi).col
+                }
+                else {
+                    text.chars().count() + 1
+                };
+                let pad = display_width(text, start.col);
+                let len = cmp::max(display_width(text, end_col).saturating_sub(pad), 1);
+                let (mark, style) = if a.primary {
+                    ("^", Emitter::severity_style(diagnostic.severity))
+                }
+                else {
+                    ("-", BLUE)
+                };
+                let mut marker = mark.repeat(len);
+                if !a.label.is_empty() {
+                    marker.push(' ');
+                    marker.push_str(&a.label);
+                }
+                out.push_str(&format!("{} {}{}\n", gutter(""), " ".repeat(pad), self.paint(style, &marker)));
+            }
+        }
+
+        for note in &diagnostic.notes {
+            out.push_str(&format!("{}{} note: {}\n", " ".repeat(width + 1), self.paint(BLUE, "="), note));
+        }
+        out
+    }
+}
+
+//Tabs are shown as four spaces so that the markers line up with the source line
+const TAB: &str = "    ";
+
+//The width the text of a line takes up before the 1-based column `col`
+fn display_width(text: &str, col: usize) -> usize {
+    text.chars().take(col.saturating_sub(1)).map(|c| if c == '\t' {TAB.len()} else {1}).sum()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_span(file: &SourceFile, span: Span, primary: bool, label: Option<&str>) -> String {
+    let lo = file.line_col(span.lo);
+    let hi = file.line_col(span.hi);
+    let label = match label {
+        Some(l) => json_string(l),
+        None => "null".to_string(),
+    };
+    format!("{{\"file\":{},\"byte_start\":{},\"byte_end\":{},\"line_start\":{},\"column_start\":{},\"line_end\":{},\"column_end\":{},\"is_primary\":{},\"label\":{}}}",
+        json_string(&file.name), span.lo, span.hi, lo.line, lo.col, hi.line, hi.col, primary, label)
+}
+
+//A single line JSON object, so that every diagnostic is one line of output
+pub fn render_json(file: &SourceFile, diagnostic: &Diagnostic) -> String {
+    let mut spans = Vec::new();
+    if let Some(span) = diagnostic.span {
+        spans.push(json_span(file, span, true, None));
+    }
+    for (span, label) in &diagnostic.labels {
+        spans.push(json_span(file, *span, false, Some(label)));
+    }
+    let ty = |t: &Option<Box<Types>>| match t {
+        Some(t) => json_string(&t.to_string()),
+        None => "null".to_string(),
+    };
+    let notes: Vec<String> = diagnostic.notes.iter().map(|n| json_string(n)).collect();
+    format!("{{\"code\":{},\"severity\":{},\"message\":{},\"expected\":{},\"found\":{},\"spans\":[{}],\"notes\":[{}]}}",
+        json_string(diagnostic.code.code()), json_string(&diagnostic.severity.to_string()), json_string(&diagnostic.message),
+        ty(&diagnostic.expected), ty(&diagnostic.found), spans.join(","), notes.join(","))
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+use lalrpop_util::ParseError;
+use lalrpop_util::lexer::Token;
+use crate::comments::open_delimiter;
+use crate::span::Span;
+use crate::typechecker::Types;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+//Every kind of diagnostic has a stable code, E01xx are syntax errors, E02xx type errors and E03xx runtime errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidToken,
+    UnexpectedEof,
+    UnexpectedToken,
+    ExtraToken,
+    InvalidLiteral,
+    UnterminatedComment,
+    MismatchedTypes,
+    UndefinedVariable,
+    UndefinedFunction,
+    InvalidOperands,
+    AssignToImmutable,
+    InvalidDeref,
+    InvalidBorrow,
+    InvalidAssignTarget,
+    ReturnTypeMismatch,
+    InvalidProgram,
+    UnknownType,
+    WrongArgumentCount,
+    BreakOutsideLoop,
+    UndeclaredLabel,
+    BreakWithValue,
+    InvalidCast,
+    LiteralOutOfRange,
+    UnknownMethod,
+    InvalidFormat,
+    DivisionByZero,
+    IntegerOverflow,
+    UnboundVariable,
+    UnknownFunction,
+    BorrowConflict,
+    InvalidReference,
+    RuntimeTypeError,
+    ArgumentCount,
+    UnsupportedNode,
+    IndexOutOfBounds,
+    OutputError,
+    Panic,
+    RecursionLimit,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidToken => "E0101",
+            ErrorCode::UnexpectedEof => "E0102",
+            ErrorCode::UnexpectedToken => "E0103",
+            ErrorCode::ExtraToken => "E0104",
+            ErrorCode::InvalidLiteral => "E0105",
+            ErrorCode::UnterminatedComment => "E0106",
+            ErrorCode::MismatchedTypes => "E0201",
+            ErrorCode::UndefinedVariable => "E0202",
+            ErrorCode::UndefinedFunction => "E0203",
+            ErrorCode::InvalidOperands => "E0204",
+            ErrorCode::AssignToImmutable => "E0205",
+            ErrorCode::InvalidDeref => "E0206",
+            ErrorCode::InvalidBorrow => "E0207",
+            ErrorCode::InvalidAssignTarget => "E0208",
+            ErrorCode::ReturnTypeMismatch => "E0209",
+            ErrorCode::InvalidProgram => "E0210",
+            ErrorCode::UnknownType => "E0211",
+            ErrorCode::WrongArgumentCount => "E0212",
+            ErrorCode::BreakOutsideLoop => "E0213",
+            ErrorCode::UndeclaredLabel => "E0214",
+            ErrorCode::BreakWithValue => "E0215",
+            ErrorCode::InvalidCast => "E0216",
+            ErrorCode::LiteralOutOfRange => "E0217",
+            ErrorCode::UnknownMethod => "E0218",
+            ErrorCode::InvalidFormat => "E0219",
+            ErrorCode::DivisionByZero => "E0301",
+            ErrorCode::IntegerOverflow => "E0302",
+            ErrorCode::UnboundVariable => "E0303",
+            ErrorCode::UnknownFunction => "E0304",
+            ErrorCode::BorrowConflict => "E0305",
+            ErrorCode::InvalidReference => "E0306",
+            ErrorCode::RuntimeTypeError => "E0307",
+            ErrorCode::ArgumentCount => "E0308",
+            ErrorCode::UnsupportedNode => "E0309",
+            ErrorCode::IndexOutOfBounds => "E0310",
+            ErrorCode::OutputError => "E0311",
+            ErrorCode::Panic => "E0312",
+            ErrorCode::RecursionLimit => "E0313",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+//A problem found in a program, pointing at the code that caused it
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub expected: Option<Box<Types>>,
+    pub found: Option<Box<Types>>,
+    //Secondary spans with a label explaining their part in the problem
+    pub labels: Vec<(Span, String)>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: &str, span: Span) -> Diagnostic {
+        Diagnostic {
+            code,
+            severity: Severity::Error,
+            message: message.to_string(),
+            span: Some(span),
+            expected: None,
+            found: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    //For problems that are not caused by a particular part of the program
+    pub fn unlocated(code: ErrorCode, message: &str) -> Diagnostic {
+        let mut d = Diagnostic::error(code, message, Span::default());
+        d.span = None;
+        d
+    }
+
+    pub fn with_types(mut self, expected: &Types, found: &Types) -> Diagnostic {
+        self.expected = Some(Box::new(expected.clone()));
+        self.found = Some(Box::new(found.clone()));
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, label: &str) -> Diagnostic {
+        self.labels.push((span, label.to_string()));
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        if let (Some(expected), Some(found)) = (&self.expected, &self.found) {
+            write!(f, " (expected `{}`, found `{}`)", expected, found)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+//Turns the terminals LALRPOP reports as expected into the way they are written in a program
+fn describe_expected(expected: &[String]) -> String {
+    let mut names: Vec<String> = Vec::new();
+    for e in expected {
+        let name = if e.starts_with("r#") {
+            if e.contains("[0-9]+") {"number".to_string()}
+            else if e.contains("///") {"doc comment".to_string()}
+            else if e.starts_with("r#\"\\\"") {"string literal".to_string()}
+            else if e.ends_with("'\"#") {"char literal".to_string()}
+            else {"identifier".to_string()}
+        }
+        else {
+            format!("`{}`", e.trim_matches('"').replace("\\\"", "\""))
+        };
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    match names.len() {
+        0 => "expected nothing".to_string(),
+        1 => format!("expected {}", names[0]),
+        _ => format!("expected one of {}", names.join(", ")),
+    }
+}
+
+//The tokens that can end an expression inside a bracket
+fn closers(delimiter: Option<u8>) -> &'static str {
+    match delimiter {
+        Some(b'(') => "expected one of `)`, `,`",
+        Some(b'[') => "expected `]`",
+        _ => "expected one of `;`, `}`",
+    }
+}
+
+//The parser cannot tell which bracket an expression it has finished is in, and lists every token
+//that could follow some expression. The error is moved right after the expression and only lists
+//the tokens that can close the bracket around it.
+pub fn place_after_expression(source: &str, mut diagnostic: Diagnostic) -> Diagnostic {
+    let span = match diagnostic.span {
+        Some(span) if diagnostic.code == ErrorCode::UnexpectedToken && span.lo == span.hi => span,
+        _ => return diagnostic,
+    };
+    let end = source[..span.lo].trim_end().len();
+    let expected = closers(open_delimiter(source, end));
+    diagnostic.message = diagnostic.message.replacen(closers(Some(b'{')), expected, 1);
+    diagnostic.span = Some(Span::new(end, end));
+    diagnostic
+}
+
+//Errors stopping the interpreter carry the location of the node being evaluated
+pub type RuntimeError = Diagnostic;
+
+//A doc comment can only come before a function
+pub fn stray_doc_comment(span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::UnexpectedToken, "found a documentation comment that doesn't document anything", span)
+        .with_note("doc comments must come before a `fn`, use `//` for other comments")
+}
+
+//Translates the error produced by the generated parser for the source it was parsing
+pub fn parse_error(source: &str, error: ParseError<usize, Token, Diagnostic>) -> Diagnostic {
+    match error {
+        ParseError::InvalidToken {location} => {
+            let len = source[location..].chars().next().map_or(0, |c| c.len_utf8());
+            Diagnostic::error(ErrorCode::InvalidToken, "invalid token", Span::new(location, location + len))
+        }
+        ParseError::UnrecognizedEOF {location, expected} =>
+            Diagnostic::error(ErrorCode::UnexpectedEof, &format!("{}, found end of file", describe_expected(&expected)), Span::new(location, location)),
+        ParseError::UnrecognizedToken {token: (lo, t, hi), expected: _} if t.1.starts_with("///") => stray_doc_comment(Span::new(lo, hi)),
+        ParseError::UnrecognizedToken {token: (lo, t, hi), expected} if expected.iter().any(|e| e == "\"||\"") =>
+            Diagnostic::error(ErrorCode::UnexpectedToken, &format!("{} after expression, found `{}`", closers(Some(b'{')), t), Span::new(lo, lo))
+                .with_label(Span::new(lo, hi), "unexpected token"),
+        ParseError::UnrecognizedToken {token: (lo, t, hi), expected} =>
+            Diagnostic::error(ErrorCode::UnexpectedToken, &format!("{}, found `{}`", describe_expected(&expected), t), Span::new(lo, hi)),
+        ParseError::ExtraToken {token: (lo, t, hi)} =>
+            Diagnostic::error(ErrorCode::ExtraToken, &format!("unexpected `{}` after the end of the program", t), Span::new(lo, hi)),
+        ParseError::User {error} => error,
+    }
+}
@@ -1,12 +1,52 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::collections::HashSet;
-use crate::ast::Node;
-use crate::ast::Opcode;
-
-#[derive(Clone)]
-pub struct FnContext{
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use crate::ast::{Node, NodeKind};
+use crate::ast::{IntType, Opcode, TypeExpr};
+use crate::builtins::{prelude, Builtin, Builtins};
+use crate::typechecker::Types;
+use crate::errors::{Diagnostic, ErrorCode, RuntimeError};
+use crate::literals::{format_pieces, FormatPiece};
+use crate::span::Span;
+
+pub struct FnContext<'a>{
     fn_env: HashMap<String, FnInfo>,
+    options: Options,
+    //Where `print!` and `println!` write to
+    out: Box<dyn Write + 'a>,
+    builtins: Builtins,
+    //The number of calls to program functions being evaluated
+    depth: usize,
+    //The integer type of the place the value being evaluated is stored in, which untyped literal
+    //arithmetic is done in. `i32` if nothing decides it.
+    literal_type: Option<IntType>,
+    //The integer return type of the function being evaluated
+    ret_type: Option<IntType>,
+}
+
+//Calls nested deeper than this stop the program. Evaluating a call takes more stack than the
+//thread of the host might have, so the stack is grown while there is less than RED_ZONE left.
+const MAX_CALL_DEPTH: usize = 10000;
+const RED_ZONE: usize = 1024 * 1024;
+const STACK_GROWTH: usize = 16 * 1024 * 1024;
+
+//What happens when integer arithmetic overflows
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OverflowMode{
+    //Stop with a runtime error, like Rust debug builds
+    #[default]
+    Trap,
+    //Wrap around in two's complement, like Rust release builds
+    Wrap,
+}
+
+//Settings for running a program
+#[derive(Clone, Debug, Default)]
+pub struct Options{
+    pub overflow: OverflowMode,
 }
 
 pub struct VarContext{
@@ -14,8 +54,10 @@ pub struct VarContext{
 }
 
 #[derive(Clone)]
+#[allow(clippy::vec_box)]
 struct FnInfo{
     params: Vec<Box<Node>>,
+    ret: Option<Box<Node>>,
     instructions: Vec<Box<Node>>,
 }
 
@@ -24,16 +66,82 @@ struct VarInfo{
     value: Value,
     borrows: VecDeque<Borrow>,
     borrow_of: Option<String>,
+    //The integer type of a variable declared without a value
+    declared: Option<IntType>,
 }
 
 #[derive(Clone, Debug)]
 pub enum Value{
-    Number(i32),
+    //An integer and its type, None for a literal whose type is decided by where it ends up
+    Int(i128, Option<IntType>),
+    Float(f64),
+    Char(char),
+    //Both string literals and built strings
+    Str(String),
     Boolean(bool),
     RefValue,
+    Unit,
+    //The value of a variable declared without one
     NoValue,
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n, _) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::RefValue => write!(f, "<reference>"),
+            Value::Unit => write!(f, "()"),
+            Value::NoValue => write!(f, "<uninitialized>"),
+        }
+    }
+}
+
+impl Value {
+    //Whether two values of the same type are equal, integers are compared by value
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a, _), Value::Int(b, _)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Unit, Value::Unit) => true,
+            _ => false,
+        }
+    }
+
+    //The value as `{:?}` shows it, with quotes around strings and chars
+    pub fn debug(&self) -> String {
+        match self {
+            Value::Str(s) => format!("{:?}", s),
+            Value::Char(c) => format!("{:?}", c),
+            Value::Float(x) => format!("{:?}", x),
+            v => v.to_string(),
+        }
+    }
+}
+
+//Why the evaluation of a node stopped early, unwinding until a node that handles it
+#[derive(Clone, Debug)]
+pub enum ControlFlow{
+    //A `return` unwinding to the function call, with the location of the returned value
+    Return(Value, Span),
+    //A `break` or `continue` unwinding to the innermost loop, or the loop with the label
+    Break(Option<String>, Value),
+    Continue(Option<String>),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for ControlFlow{
+    fn from(e: RuntimeError) -> ControlFlow{
+        ControlFlow::Error(e)
+    }
+}
+
 #[derive(Clone, Debug)]
 struct Borrow{
     name: String,
@@ -41,12 +149,16 @@ struct Borrow{
 }
 
 impl VarContext{
+    #[allow(clippy::ptr_arg)]
     fn insert_borrow(&mut self, borrow_name: &String, id: &String, is_mut: bool){
         let brws = VecDeque::new();
-        let mut var_info = VarInfo{value: Value::NoValue, borrows: brws, borrow_of: None};
+        let mut var_info = VarInfo{value: Value::NoValue, borrows: brws, borrow_of: None, declared: None};
         let brw = Borrow {name: borrow_name.clone(), mutable: is_mut};
 
-        let borrow_var_info = VarInfo{value: Value::NoValue, borrows: VecDeque::new(), borrow_of: Some(id.clone())};
+        let borrow_var_info = VarInfo{value: Value::NoValue, borrows: VecDeque::new(), borrow_of: Some(id.clone()), declared: None};
+        if self.var_env.is_empty() {
+            self.add_scope();
+        }
         self.var_env[0].insert(borrow_name.clone(), borrow_var_info);
 
         for i in &mut self.var_env {
@@ -71,7 +183,7 @@ impl VarContext{
 
     fn remove_mut_borrow(&mut self, id: &String){
         let brws = VecDeque::new();
-        let mut var_info = VarInfo{value: Value::NoValue, borrows: brws, borrow_of: None};
+        let mut var_info = VarInfo{value: Value::NoValue, borrows: brws, borrow_of: None, declared: None};
         let mut mut_found = false;
 
         for i in &mut self.var_env{
@@ -103,7 +215,7 @@ impl VarContext{
 
     fn update(&mut self, id: &String, v: &Value){
         let brws = VecDeque::new();
-        let mut var_info = VarInfo{value: Value::NoValue, borrows: brws, borrow_of: None};
+        let mut var_info = VarInfo{value: Value::NoValue, borrows: brws, borrow_of: None, declared: None};
 
         for i in &mut self.var_env {
             if i.contains_key(id){
@@ -122,6 +234,7 @@ impl VarContext{
         }
     }
 
+    #[allow(clippy::needless_return)]
     fn get(&self, id: &String) -> Option<VarInfo> {
         for i in &self.var_env {
             if i.contains_key(id){
@@ -131,9 +244,10 @@ impl VarContext{
         return None;
     }
 
+    #[allow(clippy::len_zero, clippy::ptr_arg)]
     fn insert(&mut self, id: &String, v: &Value) {
         let brws = VecDeque::new();
-        let mut var_info = VarInfo {value: Value::NoValue, borrows: brws, borrow_of: None};
+        let mut var_info = VarInfo {value: Value::NoValue, borrows: brws, borrow_of: None, declared: None};
         var_info.value = v.clone();
         let s = id.clone();
 
@@ -147,6 +261,14 @@ impl VarContext{
         }
     }
 
+    //A variable declared without a value, which gets the integer type it is declared with
+    fn declare(&mut self, id: &String, t: Option<IntType>){
+        self.insert(id, &Value::NoValue);
+        if let Some(info) = self.var_env.front_mut().and_then(|scope| scope.get_mut(id)){
+            info.declared = t;
+        }
+    }
+
     fn add_scope(&mut self){
         let map: HashMap<String, VarInfo>  = HashMap::new();
         self.var_env.push_front(map);
@@ -155,176 +277,484 @@ impl VarContext{
     fn remove_scope(&mut self){
         self.var_env.pop_front();
     }
+
+    //Empties the innermost scope while keeping its allocation
+    fn clear_scope(&mut self){
+        if let Some(scope) = self.var_env.front_mut(){
+            scope.clear();
+        }
+    }
 }
 
+#[allow(clippy::needless_return)]
 pub fn interp_context() -> VarContext{
     let v = VecDeque::new();
     let c: VarContext = VarContext {var_env: v};
     return c;
 }
 
-pub fn interp_fn_context() -> FnContext{
+#[allow(clippy::needless_return)]
+pub fn interp_fn_context() -> FnContext<'static>{
     let map = HashMap::new();
-    let f = FnContext {fn_env: map};
+    let f = FnContext {fn_env: map, options: Options::default(), out: Box::new(io::stdout()), builtins: prelude(), depth: 0, literal_type: None, ret_type: None};
     return f;
 }
 
-pub fn interpret_op(node1: &Node, operation: &Opcode, node2: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-    let left = interpret(node1, vars, funcs);
-    let right = interpret(node2, vars, funcs);
-    let ret;
+fn lookup(vars: &VarContext, id: &String, span: Span) -> Result<VarInfo, RuntimeError>{
+    match vars.get(id){
+        Some(v) => Ok(v),
+        None => Err(Diagnostic::error(ErrorCode::UnboundVariable, &format!("`{}` has no value here", id), span)),
+    }
+}
+
+fn expect_int(value: &Value, span: Span) -> Result<(i128, Option<IntType>), RuntimeError>{
+    match value{
+        Value::Int(n, t) => Ok((*n, *t)),
+        v => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("expected a number, found `{}`", v), span)),
+    }
+}
 
-    if left.is_err() || right.is_err(){
-        return Err("error");
+fn expect_boolean(value: &Value, span: Span) -> Result<bool, RuntimeError>{
+    match value{
+        Value::Boolean(b) => Ok(*b),
+        v => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("expected a boolean, found `{}`", v), span)),
     }
+}
 
-    let number = match left.clone().unwrap(){
-        Value::Number(_n) => true,
+//The name and type of a parameter
+//`&str` parameters are passed by value, a `String` or a reference to one being borrowed as one
+fn is_str_ref(t: &TypeExpr) -> bool{
+    match t{
+        TypeExpr::Ref(t) => **t == TypeExpr::Named("str".to_string()),
         _ => false,
+    }
+}
+
+//The value an argument refers to when it is only read through a reference, like a `&String`
+//given for a `&str`
+fn interpret_borrowed(node: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    match &node.kind{
+        NodeKind::UnaryOp(Opcode::Ref, n) | NodeKind::UnaryOp(Opcode::MutRef, n) => interpret_borrowed(n, vars, funcs),
+        NodeKind::ID(s) if lookup(vars, s, node.span)?.borrow_of.is_some() => {
+            let deref = Node::new(NodeKind::UnaryOp(Opcode::DeRef, Box::new(node.clone())), node.span);
+            interpret(&deref, vars, funcs)
+        },
+        _ => interpret(node, vars, funcs),
+    }
+}
+
+//Evaluates an expression whose value is stored as the given integer type
+fn interpret_as(node: &Node, t: Option<IntType>, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let outer = std::mem::replace(&mut funcs.literal_type, t);
+    let value = interpret(node, vars, funcs);
+    funcs.literal_type = outer;
+    value
+}
+
+//The integer type an expression has before evaluating it, if its operands or its callee decide it
+fn static_int_type(node: &Node, vars: &VarContext, funcs: &FnContext) -> Option<IntType>{
+    match &node.kind{
+        NodeKind::Number(_, t) => *t,
+        NodeKind::ID(s) => int_type_of_var(vars, s),
+        NodeKind::Cast(_, t) => match &t.kind{
+            NodeKind::Type(t) => int_type(t),
+            _ => None,
+        },
+        NodeKind::Call(f, _) => match funcs.fn_env.get(f){
+            Some(f) => int_type_of(&f.ret),
+            None => match funcs.builtins.get(f).map(|b| &b.ret){
+                Some(Types::Int(t)) => Some(*t),
+                _ => None,
+            },
+        },
+        NodeKind::MethodCall(_, m, _) if m == "len" => Some(IntType::Usize),
+        NodeKind::Index(..) => Some(IntType::U8),
+        NodeKind::UnaryOp(Opcode::UnarySub, n) | NodeKind::BlockValue(n) => static_int_type(n, vars, funcs),
+        NodeKind::Op(a, o, b) if !compares(o) => static_int_type(a, vars, funcs).or_else(|| static_int_type(b, vars, funcs)),
+        _ => None,
+    }
+}
+
+fn compares(operation: &Opcode) -> bool{
+    matches!(operation, Opcode::Less | Opcode::LessOrEq | Opcode::Greater | Opcode::GreaterorEq | Opcode::Equals | Opcode::Neq)
+}
+
+//Whether each reference a parameter type goes through is mutable, outermost first
+fn reference_levels(t: &TypeExpr) -> Vec<bool>{
+    let mut levels = Vec::new();
+    let mut t = t;
+    loop{
+        match t{
+            t if is_str_ref(t) => return levels,
+            TypeExpr::Ref(inner) => {
+                levels.push(false);
+                t = inner;
+            },
+            TypeExpr::MutRef(inner) => {
+                levels.push(true);
+                t = inner;
+            },
+            _ => return levels,
+        }
+    }
+}
+
+fn param_def(node: &Node) -> Result<(&String, &TypeExpr), RuntimeError>{
+    match &node.kind{
+        NodeKind::ParamDef(name, t) => match &t.kind{
+            NodeKind::Type(t) => Ok((name, t)),
+            _ => Err(Diagnostic::error(ErrorCode::UnsupportedNode, "expected a type", t.span)),
+        },
+        _ => Err(Diagnostic::error(ErrorCode::UnsupportedNode, "expected a parameter definition", node.span)),
+    }
+}
+
+//The result of an arithmetic operation, None if it overflowed and overflow traps
+//Literals that are not typed yet are only checked once they get a type
+fn fit(value: Option<i128>, wrapped: i128, t: Option<IntType>, mode: OverflowMode) -> Option<Value>{
+    let t = match t{
+        Some(t) => t,
+        None => return value.map(|v| Value::Int(v, None)),
     };
+    match (value, mode){
+        (Some(v), _) if t.contains(v) => Some(Value::Int(v, Some(t))),
+        (_, OverflowMode::Trap) => None,
+        (_, OverflowMode::Wrap) => Some(Value::Int(t.wrap(wrapped), Some(t))),
+    }
+}
 
+//The integer type a type annotation names, if it names one
+fn int_type(t: &TypeExpr) -> Option<IntType>{
+    match t{
+        TypeExpr::Named(name) => IntType::from_name(name),
+        _ => None,
+    }
+}
 
-    if number{
-        let int1 = match left.clone().unwrap(){
-            Value::Number(n) => n,
-            _ => panic!("error"),
-        };
-    
-        let int2 = match right.clone().unwrap(){
-            Value::Number(n) => n,
-            _ => panic!("error"),
-        };
-        ret = match operation{
-            Opcode::Add => Value::Number(int1 + int2),
-            Opcode::Sub => Value::Number(int1 - int2),
-            Opcode::Mul => Value::Number(int1 * int2),
-            Opcode::Div => Value::Number(int1 / int2),
-            Opcode::Mod => Value::Number(int1 % int2),
-            Opcode::Less => Value::Boolean(int1 < int2),
-            Opcode::LessOrEq => Value::Boolean(int1 <= int2),
-            Opcode::Greater => Value::Boolean(int1 > int2),
-            Opcode::GreaterorEq => Value::Boolean(int1 >= int2),
-            Opcode::Equals => Value::Boolean(int1 == int2),
-            Opcode::Neq => Value::Boolean(int1 != int2),
-            _ => panic!("Unrecognized operation for i32"),
+//Gives a literal the type it is stored as, `i32` if nothing decides it. An integer of another
+//type has to fit in the type it is stored as too.
+fn coerce(value: Value, t: Option<IntType>, span: Span, mode: OverflowMode) -> Result<Value, RuntimeError>{
+    let (n, t) = match (value, t){
+        (Value::Int(n, None), t) => (n, t.unwrap_or(IntType::I32)),
+        (Value::Int(n, Some(from)), Some(t)) if from != t => (n, t),
+        (v, _) => return Ok(v),
+    };
+    match fit(Some(n), n, Some(t), mode){
+        Some(v) => Ok(v),
+        None => Err(Diagnostic::error(ErrorCode::IntegerOverflow, &format!("`{}` does not fit in `{}`", n, t), span)),
+    }
+}
+
+//The integer type of a type node, if it names one
+fn int_type_of(node: &Option<Box<Node>>) -> Option<IntType>{
+    match node.as_ref().map(|n| &n.kind){
+        Some(NodeKind::Type(t)) => int_type(t),
+        _ => None,
+    }
+}
+
+//The integer type of the value a variable holds, or the one it was declared with
+fn int_type_of_var(vars: &VarContext, id: &String) -> Option<IntType>{
+    let info = vars.get(id)?;
+    match info.value{
+        Value::Int(_, t) => t.or(info.declared),
+        _ => info.declared,
+    }
+}
+
+//Arithmetic on untyped literals is done in the type of the other operand, or of the place the result
+//is stored in. The operands of a comparison are not stored, so they default to `i32`.
+#[allow(clippy::needless_late_init, clippy::needless_return)]
+pub fn interpret_op(node1: &Node, operation: &Opcode, node2: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let stored = if compares(operation) {None} else {funcs.literal_type};
+    let literal_type = static_int_type(node1, vars, funcs).or_else(|| static_int_type(node2, vars, funcs)).or(stored);
+    let left = interpret_as(node1, literal_type, vars, funcs)?;
+    let right = interpret_as(node2, literal_type, vars, funcs)?;
+    let span = node1.span.to(node2.span);
+    let ret;
+
+    //There is only one unit value, so unit values are always equal
+    if let (Value::Unit, Value::Unit) = (&left, &right){
+        return match operation{
+            Opcode::Equals => Ok(Value::Boolean(true)),
+            Opcode::Neq => Ok(Value::Boolean(false)),
+            _ => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` cannot be applied to `()`", operation), span).into()),
         };
+    }
 
+    if let (Value::Float(x1), Value::Float(x2)) = (&left, &right){
+        return interpret_float_op(*x1, operation, *x2, span);
     }
-    else{
-        let bool1 = match left.clone().unwrap(){
-            Value::Boolean(b) => b,
-            _ => panic!("unrecognized type"),
+    if let (Value::Str(s1), Value::Str(s2)) = (&left, &right){
+        return match operation{
+            Opcode::Add => Ok(Value::Str(format!("{}{}", s1, s2))),
+            Opcode::Equals => Ok(Value::Boolean(s1 == s2)),
+            Opcode::Neq => Ok(Value::Boolean(s1 != s2)),
+            _ => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` cannot be applied to strings", operation), span).into()),
         };
+    }
+    if let (Value::Char(c1), Value::Char(c2)) = (&left, &right){
+        return match operation{
+            Opcode::Less => Ok(Value::Boolean(c1 < c2)),
+            Opcode::LessOrEq => Ok(Value::Boolean(c1 <= c2)),
+            Opcode::Greater => Ok(Value::Boolean(c1 > c2)),
+            Opcode::GreaterorEq => Ok(Value::Boolean(c1 >= c2)),
+            Opcode::Equals => Ok(Value::Boolean(c1 == c2)),
+            Opcode::Neq => Ok(Value::Boolean(c1 != c2)),
+            _ => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` cannot be applied to chars", operation), span).into()),
+        };
+    }
+
+    let number = matches!(left, Value::Int(..));
 
-        let bool2 = match right.clone().unwrap(){
-            Value::Boolean(b) => b,
-            _ => panic!("unrecognized type"),
+
+    if number{
+        let (int1, t1) = expect_int(&left, node1.span)?;
+        let (int2, t2) = expect_int(&right, node2.span)?;
+        //A literal takes the type of the other operand
+        let t = Some(t1.or(t2).or(literal_type).unwrap_or(IntType::I32));
+        let divides = matches!(operation, Opcode::Div | Opcode::Mod);
+        if divides && int2 == 0{
+            return Err(Diagnostic::error(ErrorCode::DivisionByZero, &format!("attempt to calculate `{} {} 0`", int1, operation), span).into());
+        }
+        let mode = funcs.options.overflow;
+        //`MIN % -1` overflows like `MIN / -1`, even though the remainder is 0
+        let rem_overflows = t.is_some_and(|t| t.signed() && int1 == t.min() && int2 == -1);
+        let result = match operation{
+            Opcode::Add => fit(int1.checked_add(int2), int1.wrapping_add(int2), t, mode),
+            Opcode::Sub => fit(int1.checked_sub(int2), int1.wrapping_sub(int2), t, mode),
+            Opcode::Mul => fit(int1.checked_mul(int2), int1.wrapping_mul(int2), t, mode),
+            Opcode::Div => fit(int1.checked_div(int2), int1.wrapping_div(int2), t, mode),
+            Opcode::Mod => fit(int1.checked_rem(int2).filter(|_| !rem_overflows), 0, t, mode),
+            Opcode::Less => Some(Value::Boolean(int1 < int2)),
+            Opcode::LessOrEq => Some(Value::Boolean(int1 <= int2)),
+            Opcode::Greater => Some(Value::Boolean(int1 > int2)),
+            Opcode::GreaterorEq => Some(Value::Boolean(int1 >= int2)),
+            Opcode::Equals => Some(Value::Boolean(int1 == int2)),
+            Opcode::Neq => Some(Value::Boolean(int1 != int2)),
+            _ => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` cannot be applied to numbers", operation), span).into()),
+        };
+        ret = match result{
+            Some(v) => v,
+            None => return Err(Diagnostic::error(ErrorCode::IntegerOverflow, &format!("attempt to calculate `{} {} {}` with overflow", int1, operation, int2), span).into()),
         };
 
+    }
+    else{
+        let bool1 = expect_boolean(&left, node1.span)?;
+        let bool2 = expect_boolean(&right, node2.span)?;
+
         ret = match operation{
             Opcode::And => Value::Boolean(bool1 && bool2),
             Opcode::Or => Value::Boolean(bool1 || bool2),
             Opcode::Equals => Value::Boolean(bool1 == bool2),
             Opcode::Neq => Value::Boolean(bool1 != bool2),
-            _ => panic!("unrecognized type for boolean"),
+            _ => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` cannot be applied to booleans", operation), span).into()),
         }
     }
 
     return Ok(ret);
 }
 
-pub fn interpret_program(functions: &Vec<Box<Node>>) -> Result<Value, &'static str>{
+//Floats follow IEEE 754, so dividing by zero gives an infinity or NaN instead of an error
+fn interpret_float_op(x1: f64, operation: &Opcode, x2: f64, span: Span) -> Result<Value, ControlFlow>{
+    let ret = match operation{
+        Opcode::Add => Value::Float(x1 + x2),
+        Opcode::Sub => Value::Float(x1 - x2),
+        Opcode::Mul => Value::Float(x1 * x2),
+        Opcode::Div => Value::Float(x1 / x2),
+        Opcode::Mod => Value::Float(x1 % x2),
+        Opcode::Less => Value::Boolean(x1 < x2),
+        Opcode::LessOrEq => Value::Boolean(x1 <= x2),
+        Opcode::Greater => Value::Boolean(x1 > x2),
+        Opcode::GreaterorEq => Value::Boolean(x1 >= x2),
+        Opcode::Equals => Value::Boolean(x1 == x2),
+        Opcode::Neq => Value::Boolean(x1 != x2),
+        _ => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` cannot be applied to floats", operation), span).into()),
+    };
+    Ok(ret)
+}
+
+pub fn interpret_program(functions: &Vec<Box<Node>>) -> Result<Value, RuntimeError>{
+    interpret_program_with(functions, &Options::default())
+}
+
+pub fn interpret_program_with(functions: &Vec<Box<Node>>, options: &Options) -> Result<Value, RuntimeError>{
+    interpret_program_to(functions, options, &mut io::stdout())
+}
+
+//Runs a program with the output of `print!` and `println!` going to `out`
+pub fn interpret_program_to(functions: &Vec<Box<Node>>, options: &Options, out: &mut dyn Write) -> Result<Value, RuntimeError>{
+    interpret_program_with_builtins(functions, options, &prelude(), out)
+}
+
+//Runs a program that can call the given builtins, which should be the ones it was type checked with
+pub fn interpret_program_with_builtins(functions: &Vec<Box<Node>>, options: &Options, builtins: &Builtins, out: &mut dyn Write) -> Result<Value, RuntimeError>{
     let map = HashMap::new();
-    let mut funcs = FnContext{fn_env: map};
+    let mut funcs = FnContext{fn_env: map, options: options.clone(), out: Box::new(out), builtins: builtins.clone(), depth: 0, literal_type: None, ret_type: None};
     for f in functions{
-        match &**f{
-            Node::FnDef(id, params, _ret, instr) => func_definition(id, params, instr, &mut funcs), //Inserting info in the FnContext
-            _ => panic!("invalid program"), 
+        match &f.kind{
+            NodeKind::FnDef(id, params, ret, instr, _) => func_definition(id, params, ret, instr, &mut funcs), //Inserting info in the FnContext
+            _ => return Err(Diagnostic::error(ErrorCode::UnsupportedNode, "expected a function definition", f.span)), 
         }
     }
     let a_var_env = VecDeque::new();
     let mut vars = VarContext{var_env: a_var_env}; //Setting up an empty context
     let v = Vec::new();
     let s: String = "main".to_string();
-    let x = interpret_call_wrapper(&s, &v, &mut funcs, &mut vars);
-    return x;
+    if !funcs.fn_env.contains_key(&s){
+        return Err(Diagnostic::unlocated(ErrorCode::UnknownFunction, "the program has no `main` function"));
+    }
+    let x = interpret_call_wrapper(&s, &v, Span::default(), &mut funcs, &mut vars);
+    match x{
+        Ok(v) => Ok(v),
+        Err(ControlFlow::Return(v, _)) => Ok(v),
+        Err(ControlFlow::Error(e)) => Err(e),
+        Err(_) => Err(Diagnostic::unlocated(ErrorCode::UnsupportedNode, "`break` or `continue` outside of a loop")),
+    }
 }
 
 
-pub fn func_definition(fn_name: &String, _params: &Vec<Box<Node>>, _instructions: &Vec<Box<Node>>, funcs: &mut FnContext){
-    let fn_info = FnInfo {params: _params.clone(), instructions: _instructions.clone()};
+#[allow(clippy::ptr_arg)]
+pub fn func_definition(fn_name: &String, _params: &Vec<Box<Node>>, _ret: &Option<Box<Node>>, _instructions: &Vec<Box<Node>>, funcs: &mut FnContext){
+    let fn_info = FnInfo {params: _params.clone(), ret: _ret.clone(), instructions: _instructions.clone()};
     funcs.fn_env.insert(fn_name.clone(), fn_info);
 }
 
-fn get_param_name(node: &Node) -> String{
-    let s = match node{
-        Node::ParamDef(name, _typespec) => name,
-        _ => panic!("called on params only"),
-    };
-    return s.clone();
-}
-
 //Change map will map mutable references in a call to updated values. These will be updated in the callers context.
-fn interpret_call_wrapper(func_name: &String, args: &Vec<Box<Node>>, funcs: &mut FnContext, vars: &mut VarContext) -> Result<Value, &'static str>{
+#[allow(clippy::needless_late_init)]
+fn interpret_call_wrapper(func_name: &String, args: &[Box<Node>], span: Span, funcs: &mut FnContext, vars: &mut VarContext) -> Result<Value, ControlFlow>{
+    if !funcs.fn_env.contains_key(func_name){
+        if let Some(builtin) = funcs.builtins.get(func_name).cloned(){
+            return interpret_builtin_call(func_name, &builtin, args, span, funcs, vars);
+        }
+    }
+    if funcs.depth >= MAX_CALL_DEPTH{
+        return Err(Diagnostic::error(ErrorCode::RecursionLimit, &format!("recursion limit reached while calling `{}`", func_name), span)
+            .with_note(&format!("calls can only be nested {} deep", MAX_CALL_DEPTH)).into());
+    }
     let mut change_map: HashMap<String, Value> = HashMap::new();
-    let result = interpret_call(func_name, args, funcs, vars, &mut change_map);
-    let fn_info = funcs.fn_env.get_mut(func_name).unwrap().clone();
+    funcs.depth += 1;
+    let outer = (funcs.literal_type, funcs.ret_type);
+    funcs.literal_type = None;
+    funcs.ret_type = funcs.fn_env.get(func_name).and_then(|f| int_type_of(&f.ret));
+    let result = stacker::maybe_grow(RED_ZONE, STACK_GROWTH, || interpret_call(func_name, args, span, funcs, vars, &mut change_map));
+    (funcs.literal_type, funcs.ret_type) = outer;
+    funcs.depth -= 1;
+    let result = result?;
+    let fn_info = funcs.fn_env.get(func_name).unwrap().clone(); //interpret_call checked that the function exists
     let mut i = 0;
     for param in &fn_info.params{
         let mut_ref;
-        let type_spec = match &**param{
-            Node::ParamDef(_, t) => t,
-            _ => panic!("params only"),
-        };
-
-        let id = match &**param{
-            Node::ParamDef(n, _) => n,
-            _ => panic!("params only"),
-        };
+        let (id, type_spec) = param_def(param)?;
 
-        match type_spec as &str{
-            ": &mut i32" => mut_ref = true,
-            ": &mut bool" => mut_ref = true,
+        match type_spec{
+            TypeExpr::MutRef(_) => mut_ref = reference_levels(type_spec).len() == 1,
             _ => mut_ref = false,
         };
 
         if mut_ref{
-            let new_value = change_map.get(id).unwrap();
+            let new_value = match change_map.get(id){
+                Some(v) => v,
+                None => continue,
+            };
             let name_of_borrow_box = args[i].clone();
-            let name_of_borrow = match *name_of_borrow_box{
-                Node::ID(s) => s,
-                _ => panic!("reference arguments need to be identifiers"),
+            let span = name_of_borrow_box.span;
+            let name_of_borrow = match name_of_borrow_box.kind{
+                NodeKind::ID(s) => s,
+                _ => return Err(Diagnostic::error(ErrorCode::InvalidReference, "reference arguments need to be identifiers", span).into()),
             };
 
-            let borrow_var_info = vars.get(&name_of_borrow).unwrap();
-            let name_of_identifier = borrow_var_info.borrow_of.unwrap();
-            let identifier_info = vars.get(&name_of_identifier).unwrap();
-            if name_of_borrow != identifier_info.borrows[0].name{
-                panic!("mutable reference and non mutable references exist");
+            let borrow_var_info = lookup(vars, &name_of_borrow, span)?;
+            let name_of_identifier = match borrow_var_info.borrow_of{
+                Some(s) => s,
+                None => return Err(Diagnostic::error(ErrorCode::InvalidReference, &format!("`{}` is not a reference", name_of_borrow), span).into()),
+            };
+            let identifier_info = lookup(vars, &name_of_identifier, span)?;
+            let unique = match identifier_info.borrows.front(){
+                Some(b) => b.name == name_of_borrow,
+                None => false,
+            };
+            if !unique{
+                return Err(Diagnostic::error(ErrorCode::BorrowConflict, &format!("`{}` is not the only reference to `{}`", name_of_borrow, name_of_identifier), span).into());
             }
 
-            let value_node = match new_value{
-                Value::Number(n) => Node::Number(*n),
-                Value::Boolean(b) => Node::Boolean(*b),
-                _ => panic!("not a valid value"),
+            match new_value{
+                Value::Int(..) | Value::Float(_) | Value::Char(_) | Value::Str(_) | Value::Boolean(_) => (),
+                v => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("cannot write `{}` through a reference", v), span).into()),
             };
-            let write = Node::WriteByRef(Opcode::DeRef, name_of_borrow, Box::new(value_node));
-            let _res = interpret(&write, vars, funcs); //Updating the value as modified by the call
+            write_through(&name_of_borrow, new_value.clone(), span, funcs.options.overflow, vars)?; //Updating the value as modified by the call
         }
         i += 1;
     }
-    return result;
+    Ok(result)
 }
 
 
-pub fn interpret_call(func_name: &String, args: &Vec<Box<Node>>, funcs: &mut FnContext, vars: &mut VarContext, change_map: &mut HashMap<String, Value>) 
-    -> Result<Value, &'static str>{
+//Builtins get their arguments by value, literals having the type of their parameter
+fn interpret_builtin_call(func_name: &String, builtin: &Builtin, args: &[Box<Node>], span: Span, funcs: &mut FnContext, vars: &mut VarContext) -> Result<Value, ControlFlow>{
+    if builtin.params.len() != args.len(){
+        return Err(Diagnostic::error(ErrorCode::ArgumentCount, &format!("this function takes {} arguments but {} were supplied", builtin.params.len(), args.len()), span).into());
+    }
     let mut values = Vec::new();
-    for arg in args{
-        values.push(interpret(arg, vars, funcs).unwrap());
+    for (arg, t) in args.iter().zip(&builtin.params){
+        let value = match t{
+            Types::Int(t) => coerce(interpret_as(arg, Some(*t), vars, funcs)?, Some(*t), arg.span, funcs.options.overflow)?,
+            Types::Str => interpret_borrowed(arg, vars, funcs)?,
+            _ => interpret(arg, vars, funcs)?,
+        };
+        values.push(value);
+    }
+    match (builtin.run)(&values, funcs.options.overflow){
+        //Host functions are not type checked, so their results are checked against their signature
+        Ok(v) => {
+            let t = match &builtin.ret{
+                Types::Int(t) => Some(*t),
+                _ => None,
+            };
+            let v = coerce(v, t, span, funcs.options.overflow)?;
+            if !has_type(&v, &builtin.ret){
+                return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("`{}` returned `{}`, but it is declared to return `{}`", func_name, v.debug(), builtin.ret), span).into());
+            }
+            Ok(v)
+        },
+        Err(mut e) => {
+            if e.span.is_none(){
+                e.span = Some(span);
+            }
+            Err(e.into())
+        },
     }
+}
+
+//Whether a value belongs to a type, Unknown standing for any type
+fn has_type(value: &Value, t: &Types) -> bool{
+    match (value, t){
+        (_, Types::Unknown) => true,
+        (Value::Int(_, Some(a)), Types::Int(b)) => a == b,
+        (Value::Float(_), Types::Float) | (Value::Char(_), Types::Char) | (Value::Boolean(_), Types::Boolean) => true,
+        (Value::Str(_), Types::Str) | (Value::Str(_), Types::String) => true,
+        (Value::Unit, Types::UnitType) => true,
+        _ => false,
+    }
+}
 
-    let fn_info = funcs.fn_env.get_mut(func_name).unwrap().clone();
+#[allow(clippy::explicit_counter_loop, clippy::needless_late_init)]
+pub fn interpret_call(func_name: &String, args: &[Box<Node>], span: Span, funcs: &mut FnContext, vars: &mut VarContext, change_map: &mut HashMap<String, Value>) 
+    -> Result<Value, ControlFlow>{
+    let fn_info = match funcs.fn_env.get(func_name){
+        Some(f) => f.clone(),
+        None => return Err(Diagnostic::error(ErrorCode::UnknownFunction, &format!("cannot find function `{}`", func_name), span).into()),
+    };
+    if fn_info.params.len() != args.len(){
+        return Err(Diagnostic::error(ErrorCode::ArgumentCount, &format!("`{}` takes {} arguments but {} were supplied", func_name, fn_info.params.len(), args.len()), span).into());
+    }
+    let mut values = Vec::new();
+    for (arg, param) in args.iter().zip(&fn_info.params){
+        let t = param_def(param)?.1;
+        let value = if is_str_ref(t) {interpret_borrowed(arg, vars, funcs)?} else {interpret_as(arg, int_type(t), vars, funcs)?};
+        values.push(value);
+    }
     let new_var_env = VecDeque::new();
     let mut new_vars = VarContext{var_env: new_var_env};
     new_vars.add_scope();
@@ -336,48 +766,49 @@ pub fn interpret_call(func_name: &String, args: &Vec<Box<Node>>, funcs: &mut FnC
     for param in &fn_info.params{
         let mut mut_ref = false;
         let mut non_mut_ref = false;
-        let type_spec = match &**param{
-            Node::ParamDef(_, t) => t,
-            _ => panic!("params only"),
-        };
-
-        let id = match &**param{
-            Node::ParamDef(n, _) => n,
-            _ => panic!("params only"),
-        };
+        let (id, type_spec) = param_def(param)?;
 
-        match type_spec as &str{
-            ": &i32" => non_mut_ref = true,
-            ": &bool" => non_mut_ref = true,
-            ": &mut i32" => mut_ref = true,
-            ": &mut bool" => mut_ref = true,
+        match type_spec{
+            //A `&str` is passed like any other value
+            t if is_str_ref(t) => (),
+            TypeExpr::Ref(_) => non_mut_ref = true,
+            TypeExpr::MutRef(_) => mut_ref = true,
             _ => mut_ref = false,
         };
         if non_mut_ref || mut_ref{
             visited[i] = true;
-            let o = Opcode::DeRef;
-            let n = Node::UnaryOp(o, args[i].clone());
-            let val = interpret(&n, vars, funcs).unwrap();
+            let levels = reference_levels(type_spec);
+            let mut n = args[i].clone();
+            for _ in &levels{
+                n = Box::new(Node::new(NodeKind::UnaryOp(Opcode::DeRef, n), args[i].span));
+            }
+            let val = interpret(&n, vars, funcs)?;
 
 
-            let arg_name = match *args[i].clone(){
-                Node::ID(s) => s,
-                _ => panic!("unimplemented"),
+            let arg_name = match args[i].kind.clone(){
+                NodeKind::ID(s) => s,
+                _ => return Err(Diagnostic::error(ErrorCode::InvalidReference, "reference arguments need to be identifiers", args[i].span).into()),
             };
 
             if mut_ref && mut_refs.contains(&arg_name){
-                panic!("Can not have multiple mutable references to the same id as arguments");
+                return Err(Diagnostic::error(ErrorCode::BorrowConflict, &format!("`{}` is passed as a mutable reference more than once", arg_name), args[i].span).into());
             }
             else{
                 mut_refs.insert(arg_name);
             }
 
 
-            let mut b_name = id.clone(); //b_name is the id of the variable which the mut ref is pointing to
-            b_name.push_str("@DATA");
-            new_vars.insert(&b_name.clone(), &val);
-
-            new_vars.insert_borrow(&id, &b_name, mut_ref);
+            //A reference to a reference is passed as a chain, `x` pointing to `x@DATA`, which points to `x@DATA@DATA`
+            let mut names = vec![id.clone()];
+            for _ in &levels{
+                let mut b_name = names[names.len() - 1].clone(); //b_name is the id of the variable the previous reference points to
+                b_name.push_str("@DATA");
+                names.push(b_name);
+            }
+            new_vars.insert(&names[levels.len()], &val);
+            for k in (0..levels.len()).rev(){
+                new_vars.insert_borrow(&names[k], &names[k + 1], levels[k]);
+            }
 
         }
         i += 1;
@@ -386,70 +817,79 @@ pub fn interpret_call(func_name: &String, args: &Vec<Box<Node>>, funcs: &mut FnC
     new_vars.add_scope(); //Top scope of the function
     let mut i = 0;
     for _arg in args{     //In the new context the parameter name and the argument values are inserted together
-        if visited[i]{
-            continue;
+        if !visited[i]{
+            let (param_name, t) = param_def(&fn_info.params[i])?;
+            let value = coerce(values[i].clone(), int_type(t), args[i].span, funcs.options.overflow)?;
+            new_vars.insert(param_name, &value);
         }
-        let param_name = get_param_name(&fn_info.params[i]);
-        new_vars.insert(&param_name, &values[i]);
         i += 1;
     }
-    let mut ret = Ok(Value::NoValue);
+    //The returned value is converted to the return type where it was given, or at the call
+    let mut ret = Value::Unit;
+    let mut ret_span = span;
     for instr in &fn_info.instructions{
-        let _x = interpret(instr, &mut new_vars, funcs); 
-        match &**instr{
-            Node::Return(_o) => ret = interpret(instr, &mut new_vars, funcs),
-            Node::BlockValue(_v) => ret = interpret(instr, &mut new_vars, funcs),
-                _ => (),
+        let result = match &instr.kind{
+            NodeKind::BlockValue(e) => interpret_as(e, funcs.ret_type, &mut new_vars, funcs),
+            _ => interpret(instr, &mut new_vars, funcs),
+        };
+        match result{
+            Ok(v) => {
+                if let NodeKind::BlockValue(_) = &instr.kind{
+                    ret = v;
+                    ret_span = instr.span;
+                }
+            },
+            //A return anywhere in the body ends the call
+            Err(ControlFlow::Return(v, s)) => {
+                ret = v;
+                ret_span = s;
+                break;
+            },
+            Err(ControlFlow::Error(e)) => return Err(ControlFlow::Error(e)),
+            //The type checker makes sure loops never let these escape
+            Err(_) => return Err(Diagnostic::error(ErrorCode::UnsupportedNode, "`break` or `continue` outside of a loop", instr.span).into()),
         }
     }
     //Now the change_map needs to be updated
     for param in &fn_info.params{
         let mut_ref;
-        let type_spec = match &**param{
-            Node::ParamDef(_, t) => t,
-            _ => panic!("params only"),
-        };
-
-        let id = match &**param{
-            Node::ParamDef(n, _) => n,
-            _ => panic!("params only"),
-        };
+        let (id, type_spec) = param_def(param)?;
 
-        match type_spec as &str{
-            ": &mut i32" => mut_ref = true,
-            ": &mut bool" => mut_ref = true,
+        //The references a mutable reference points to cannot be changed
+        match type_spec{
+            TypeExpr::MutRef(_) => mut_ref = reference_levels(type_spec).len() == 1,
             _ => mut_ref = false,
         };
 
         if mut_ref{
             let o = Opcode::DeRef;
-            let borrow_name = Node::ID(id.clone());
-            let n = Node::UnaryOp(o, Box::new(borrow_name));
-            let val = interpret(&n, &mut new_vars, funcs).unwrap(); //Dereference the mut_ref in current context to get the value
+            let borrow_name = Node::new(NodeKind::ID(id.clone()), param.span);
+            let n = Node::new(NodeKind::UnaryOp(o, Box::new(borrow_name)), param.span);
+            let val = interpret(&n, &mut new_vars, funcs)?; //Dereference the mut_ref in current context to get the value
             change_map.insert(id.clone(), val);
         }
     }
 
-    return ret; //should return the actual returned value of the call
+    Ok(coerce(ret, int_type_of(&fn_info.ret), ret_span, funcs.options.overflow)?)
 }
 
-pub fn interpret_unary_op(operation: &Opcode, node: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-    let value = interpret(node, vars, funcs);
+#[allow(clippy::match_like_matches_macro, clippy::needless_return)]
+pub fn interpret_unary_op(operation: &Opcode, node: &Node, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let value = interpret(node, vars, funcs)?;
     let number = match operation{
         Opcode::UnarySub => true,
         _ => false,
     };
 
     if number{
-        if value.is_err(){
-            return Err("error");
+        if let Value::Float(x) = value{
+            return Ok(Value::Float(-x));
         }
-        let val = match value.clone().unwrap(){
-            Value::Number(n) => n,
-            _ => panic!("error"),
+        let (val, t) = expect_int(&value, node.span)?;
+        return match fit(val.checked_neg(), val.wrapping_neg(), t, funcs.options.overflow){
+            Some(v) => Ok(v),
+            None => Err(Diagnostic::error(ErrorCode::IntegerOverflow, &format!("attempt to negate `{}` with overflow", val), span).into()),
         };
-    
-        return Ok(Value::Number(-val));  
     }
 
     let boolop = match operation{
@@ -458,13 +898,7 @@ pub fn interpret_unary_op(operation: &Opcode, node: &Node, vars: &mut VarContext
     };
     
     if boolop{
-        if value.is_err(){
-            return Err("error");
-        }
-        let val = match value.clone().unwrap(){
-            Value::Boolean(b) => b,
-            _ => panic!("error"),
-        };
+        let val = expect_boolean(&value, node.span)?;
         return Ok(Value::Boolean(!val)); 
     }
 
@@ -474,206 +908,411 @@ pub fn interpret_unary_op(operation: &Opcode, node: &Node, vars: &mut VarContext
     };
 
     if deref{
-        let s = match node{
-            Node::ID(s) => s,
-            _ => panic!("should be unreachable"),
-        };
+        return Ok(read_through(node, span, vars)?.1);
+    }
+    return Ok(Value::RefValue); //If we are creating a reference this will signal to interpret_let or interpret_assign to handle it
+}
 
-        let var_info = vars.get(s);
-        let id = var_info.unwrap().borrow_of;
-        let value_info = vars.get(&id.clone().unwrap()).unwrap();
-        let mut non_mut_found = false;
-        for brw in value_info.borrows{                 //Check all other borrows 
-            if brw.mutable && brw.name != *s{        //If another reference exists which is mutable
-                panic!("Mutable reference and another reference exists");
-            }
-            else if !brw.mutable && !non_mut_found{                    //If a non-mutable reference is found
-                non_mut_found = true;
-                if brw.name == *s{                  //If it is our reference that is ok
-                    return Ok(value_info.value);
-                }
-                continue;
+//The variable a reference points to and its value. The reference is a variable, or for `**r`
+//the variable `*r` is.
+fn read_through(node: &Node, span: Span, vars: &VarContext) -> Result<(String, Value), RuntimeError>{
+    let name = match &node.kind{
+        NodeKind::ID(s) => s.clone(),
+        NodeKind::UnaryOp(Opcode::DeRef, n) => read_through(n, span, vars)?.0,
+        _ => return Err(Diagnostic::error(ErrorCode::InvalidReference, "only variables can be dereferenced", span)),
+    };
+    let s = &name;
+
+    let var_info = lookup(vars, s, node.span)?;
+    let id = match var_info.borrow_of{
+        Some(id) => id,
+        None => return Err(Diagnostic::error(ErrorCode::InvalidReference, &format!("`{}` is not a reference", s), span)),
+    };
+    let value_info = lookup(vars, &id, node.span)?;
+    let conflict = Diagnostic::error(ErrorCode::BorrowConflict, &format!("`{}` is used while a mutable reference to `{}` exists", s, id), span);
+    let mut non_mut_found = false;
+    for brw in value_info.borrows{                 //Check all other borrows 
+        if brw.mutable && brw.name != *s{        //If another reference exists which is mutable
+            return Err(conflict);
+        }
+        else if !brw.mutable && !non_mut_found{                    //If a non-mutable reference is found
+            non_mut_found = true;
+            if brw.name == *s{                  //If it is our reference that is ok
+                return Ok((id.clone(), value_info.value));
             }
-            else if non_mut_found && brw.name == *s{
-                if brw.mutable{                      //We know mutable and non-mutable references exist
-                    panic!("Mutable reference and another reference exists");
-                }
-                else{
-                    return Ok(value_info.value);   //Multiple non-mutable references are fine
-                }
-                
+            continue;
+        }
+        else if non_mut_found && brw.name == *s{
+            if brw.mutable{                      //We know mutable and non-mutable references exist
+                return Err(conflict);
             }
-            else if brw.name == *s{
-                return Ok(value_info.value);   //Multiple non-mutable references are fine
+            else{
+                return Ok((id.clone(), value_info.value));   //Multiple non-mutable references are fine
             }
+            
+        }
+        else if brw.name == *s{
+            return Ok((id.clone(), value_info.value));   //Multiple non-mutable references are fine
+        }
 
+    }
+    Err(Diagnostic::error(ErrorCode::InvalidReference, &format!("reference `{}` is no longer valid", s), span)
+        .with_note(&format!("`{}` was invalidated by a later mutable borrow of `{}`", s, id)))
+}
+
+//Converting between integers keeps the low bits of the value, so it truncates or sign-extends
+//like in Rust. Floats saturate at the bounds of the integer type and NaN becomes 0.
+pub fn interpret_cast(node: &Node, target: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let value = interpret(node, vars, funcs)?;
+    if let NodeKind::Type(TypeExpr::Named(name)) = &target.kind{
+        if name == "char"{
+            return match value{
+                Value::Int(n, _) => Ok(Value::Char(n as u8 as char)),
+                Value::Char(c) => Ok(Value::Char(c)),
+                v => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("cannot cast `{}` to `char`", v), node.span).into()),
+            };
         }
-        panic!("Reference not found");
+        if name == "f64"{
+            return match value{
+                Value::Int(n, _) => Ok(Value::Float(n as f64)),
+                Value::Float(x) => Ok(Value::Float(x)),
+                v => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("cannot cast `{}` to `f64`", v), node.span).into()),
+            };
+        }
+    }
+    let t = match &target.kind{
+        NodeKind::Type(t) => int_type(t),
+        _ => None,
+    };
+    let t = match t{
+        Some(t) => t,
+        None => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, "values can only be cast to integer types", target.span).into()),
+    };
+    match value{
+        Value::Int(n, _) => Ok(Value::Int(t.wrap(n), Some(t))),
+        Value::Boolean(b) => Ok(Value::Int(b as i128, Some(t))),
+        Value::Char(c) => Ok(Value::Int(t.wrap(c as i128), Some(t))),
+        //`as i128` already saturates and turns NaN into 0
+        Value::Float(x) => Ok(Value::Int((x as i128).clamp(t.min(), t.max()), Some(t))),
+        v => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("cannot cast `{}` to `{}`", v, t), node.span).into()),
+    }
+}
 
+pub fn interpret_method_call(node: &Node, method: &String, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let value = interpret(node, vars, funcs)?;
+    match (method.as_str(), value){
+        ("len", Value::Str(s)) => Ok(Value::Int(s.len() as i128, Some(IntType::Usize))),
+        (_, v) => Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("no method `{}` on `{}`", method, v), span).into()),
     }
-    return Ok(Value::RefValue); //If we are creating a reference this will signal to interpret_let or interpret_assign to handle it
 }
 
-pub fn interpret_let(id: &String, value: &Option<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-    let val = match value{
-        Some(n) => interpret(n, vars, funcs),
-        None => Ok(Value::NoValue),
+//The type checker made sure the format string is valid and matches the arguments
+pub fn interpret_print(newline: bool, format: &Node, args: &Vec<Box<Node>>, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let mut values = Vec::new();
+    //Printing a reference prints the value it points to
+    for arg in args{
+        values.push(interpret_borrowed(arg, vars, funcs)?);
+    }
+    let pieces = match &format.kind{
+        NodeKind::Str(s) => format_pieces(s).map_err(|m| Diagnostic::error(ErrorCode::RuntimeTypeError, &m, format.span))?,
+        _ => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, "format argument must be a string literal", format.span).into()),
     };
+    let mut text = String::new();
+    let mut values = values.iter();
+    for piece in pieces{
+        let value = match piece{
+            FormatPiece::Text(s) => {
+                text.push_str(&s);
+                continue;
+            },
+            _ => values.next(),
+        };
+        let value = match value{
+            Some(v) => v,
+            None => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, "more placeholders than arguments", format.span).into()),
+        };
+        match piece{
+            FormatPiece::Debug => text.push_str(&value.debug()),
+            _ => text.push_str(&value.to_string()),
+        }
+    }
+    if newline{
+        text.push('\n');
+    }
+    if let Err(e) = funcs.out.write_all(text.as_bytes()){
+        return Err(Diagnostic::error(ErrorCode::OutputError, &format!("failed to write output: {}", e), span).into());
+    }
+    Ok(Value::Unit)
+}
 
-    let create_ref = match val.clone().unwrap() {
-        Value::RefValue => true,
-        _ => false,
+//Strings are indexed by byte, like `s.as_bytes()[i]` in Rust
+pub fn interpret_index(node: &Node, index: &Node, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let value = interpret(node, vars, funcs)?;
+    let (i, _) = expect_int(&interpret(index, vars, funcs)?, index.span)?;
+    let s = match value{
+        Value::Str(s) => s,
+        v => return Err(Diagnostic::error(ErrorCode::RuntimeTypeError, &format!("cannot index into `{}`", v), node.span).into()),
     };
-    if create_ref{
-        create_reference(id, &value.as_ref().unwrap(), vars);
-        return Ok(Value::NoValue);
+    match usize::try_from(i).ok().and_then(|i| s.as_bytes().get(i)){
+        Some(b) => Ok(Value::Int(*b as i128, Some(IntType::U8))),
+        None => Err(Diagnostic::error(ErrorCode::IndexOutOfBounds, &format!("index out of bounds: the len is {} but the index is {}", s.len(), i), span).into()),
     }
-    vars.insert(id, &val.unwrap());
-    return Ok(Value::NoValue);
 }
 
-pub fn interpret_assign(id: &String, value: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-    let val = interpret(value, vars, funcs);
-    vars.update(id, &val.clone().unwrap());
-    let create_ref = match val.clone().unwrap() {
-        Value::RefValue => true,
-        _ => false,
+pub fn interpret_let(id: &String, typedef: &Option<Box<Node>>, value: &Option<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let val = match value{
+        Some(n) => coerce(interpret_as(n, int_type_of(typedef), vars, funcs)?, int_type_of(typedef), n.span, funcs.options.overflow)?,
+        None => {
+            vars.declare(id, int_type_of(typedef));
+            return Ok(Value::Unit);
+        },
     };
+
+    let create_ref = matches!(val, Value::RefValue);
     if create_ref{
-        create_reference(id, value, vars);
-        return Ok(Value::NoValue);
+        if let Some(n) = value{
+            create_reference(id, n, vars)?;
+        }
+        return Ok(Value::Unit);
+    }
+    vars.insert(id, &val);
+    Ok(Value::Unit)
+}
+
+pub fn interpret_assign(id: &String, value: &Node, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    lookup(vars, id, span)?;
+    let t = int_type_of_var(vars, id);
+    let val = coerce(interpret_as(value, t, vars, funcs)?, t, value.span, funcs.options.overflow)?;
+    vars.update(id, &val);
+    let create_ref = matches!(val, Value::RefValue);
+    if create_ref{
+        create_reference(id, value, vars)?;
+        return Ok(Value::Unit);
     }
     vars.remove_mut_borrow(id);
-    return Ok(Value::NoValue);
+    Ok(Value::Unit)
 }
 
-fn create_reference(id: &String, node: &Node, vars: &mut VarContext){
-    let op = match node{
-        Node::UnaryOp(o, _n) => o,
-        _ => panic!("unreachable"),
+#[allow(clippy::match_like_matches_macro)]
+fn create_reference(id: &String, node: &Node, vars: &mut VarContext) -> Result<(), RuntimeError>{
+    let (op, identifier) = match &node.kind{
+        NodeKind::UnaryOp(o, n) => (o, n),
+        _ => return Err(Diagnostic::error(ErrorCode::InvalidReference, "references can only be created with `&` or `&mut`", node.span)),
     };
     let is_mut = match op{
         Opcode::MutRef => true,
         _ => false,
     };
-    let identifier = match node{
-        Node::UnaryOp(_o, n) => *n.clone(),
-        _ => panic!("unreachable"),
-    };
-    let ref_name = match identifier{
-        Node::ID(s) => s,
-        _ => panic!("unreachable"),
+    let ref_name = match &identifier.kind{
+        NodeKind::ID(s) => s,
+        _ => return Err(Diagnostic::error(ErrorCode::InvalidReference, "references can only be created to variables", node.span)),
     };
-    vars.insert_borrow(id, &ref_name, is_mut);
+    vars.insert_borrow(id, ref_name, is_mut);
+    Ok(())
 
 }
 
-pub fn interpret_write_ref(name: &String, node: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-        let var_info = vars.get(name);
-        let id = var_info.unwrap().borrow_of;
-        let value_info = vars.get(&id.clone().unwrap()).unwrap();
-        let top_borrow = value_info.borrows.get(0).unwrap();
+pub fn interpret_write_ref(name: &String, node: &Node, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let t = lookup(vars, name, span)?.borrow_of.and_then(|id| int_type_of_var(vars, &id));
+    let value = interpret_as(node, t, vars, funcs)?;
+    write_through(name, value, span, funcs.options.overflow, vars)?;
+    Ok(Value::Unit)
+}
+
+//Writes a value to the variable a unique mutable reference points to
+fn write_through(name: &String, value: Value, span: Span, mode: OverflowMode, vars: &mut VarContext) -> Result<(), RuntimeError>{
+        let var_info = lookup(vars, name, span)?;
+        let id = match var_info.borrow_of{
+            Some(id) => id,
+            None => return Err(Diagnostic::error(ErrorCode::InvalidReference, &format!("`{}` is not a reference", name), span)),
+        };
+        let value_info = lookup(vars, &id, span)?;
+        let top_borrow = match value_info.borrows.front(){
+            Some(b) => b,
+            None => return Err(Diagnostic::error(ErrorCode::InvalidReference, &format!("reference `{}` is no longer valid", name), span)),
+        };
         if top_borrow.name != *name{
-            panic!("mutable reference is not unique");
+            return Err(Diagnostic::error(ErrorCode::BorrowConflict, &format!("mutable reference `{}` to `{}` is not unique", name, id), span));
         }
         else if !top_borrow.mutable{
-            panic!("Reference is not mutable");
+            return Err(Diagnostic::error(ErrorCode::InvalidReference, &format!("cannot write through `{}`, which is not a mutable reference", name), span));
         }
         else{
-            let value = interpret(node, vars, funcs).unwrap();
-            vars.update(&id.unwrap(), &value);
+            let t = match &value_info.value{
+                Value::Int(_, t) => *t,
+                _ => None,
+            };
+            let value = coerce(value, t, span, mode)?;
+            vars.update(&id, &value);
         }
-        return Ok(Value::NoValue);
+        Ok(())
 }
 
-pub fn interpret_while(condition: &Node, instr: &Vec<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext)-> Result<Value, &'static str>{
-    loop{
-        let while_condition = interpret(condition, vars, funcs);
-        let condition_value = match while_condition.unwrap(){
-            Value::Boolean(b) => b,
-            _ => panic!("while condition did not evaluate to boolean"),
-        };
-        if condition_value{
-            vars.add_scope();
-            for n in instr{
-                let _r = interpret(n, vars, funcs);
-            }
-            vars.remove_scope();
+//Runs the instructions of a block in a new scope, which is removed even if an error occurs
+//The value of a block is the value of its trailing expression, if it has one
+fn interpret_block(instr: &[Box<Node>], vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    vars.add_scope();
+    let mut result = Ok(Value::Unit);
+    for n in instr{
+        match interpret(n, vars, funcs){
+            Ok(v) => {
+                if let NodeKind::BlockValue(_) = &n.kind{
+                    result = Ok(v);
+                }
+            },
+            Err(e) => {
+                result = Err(e);
+                break;
+            },
         }
-        else{
+    }
+    vars.remove_scope();
+    result
+}
+
+//Whether a break or continue, possibly with a label, is meant for a loop
+fn targets(target: &Option<String>, label: &Option<String>) -> bool{
+    target.is_none() || target == label
+}
+
+pub fn interpret_while(label: &Option<String>, condition: &Node, instr: &[Box<Node>], vars: &mut VarContext, funcs: &mut FnContext)-> Result<Value, ControlFlow>{
+    loop{
+        let while_condition = interpret(condition, vars, funcs)?;
+        let condition_value = expect_boolean(&while_condition, condition.span)?;
+        if !condition_value{
             break;
         }
+        match interpret_block(instr, vars, funcs){
+            Ok(_) => (),
+            Err(ControlFlow::Break(l, _v)) if targets(&l, label) => break,
+            Err(ControlFlow::Continue(l)) if targets(&l, label) => continue,
+            Err(e) => return Err(e),
+        }
     }
-    return Ok(Value::NoValue);
+    Ok(Value::Unit)
 }
 
-pub fn interpret_if(condition: &Node, instr: &Vec<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext)-> Result<Value, &'static str>{
-    let if_condition = interpret(condition, vars, funcs);
-        let condition_value = match if_condition.unwrap(){
-            Value::Boolean(b) => b,
-            _ => panic!("while condition did not evaluate to boolean"),
-        };
-        if condition_value{
-            vars.add_scope();
-            for n in instr{
-                let _r = interpret(n, vars, funcs);
+pub fn interpret_loop(label: &Option<String>, instr: &[Box<Node>], vars: &mut VarContext, funcs: &mut FnContext)-> Result<Value, ControlFlow>{
+    loop{
+        match interpret_block(instr, vars, funcs){
+            Ok(_) => (),
+            Err(ControlFlow::Break(l, v)) if targets(&l, label) => return Ok(v),
+            Err(ControlFlow::Continue(l)) if targets(&l, label) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+//The scope of the loop variable is reused by every iteration
+pub fn interpret_for(label: &Option<String>, var: &String, range: &Node, instr: &Vec<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext)-> Result<Value, ControlFlow>{
+    let (start, end, inclusive, reversed) = match &range.kind{
+        NodeKind::Range(a, b, inclusive, reversed) => {
+            let start = interpret(a, vars, funcs)?;
+            let end = interpret(b, vars, funcs)?;
+            (expect_int(&start, a.span)?, expect_int(&end, b.span)?, *inclusive, *reversed)
+        },
+        _ => return Err(Diagnostic::error(ErrorCode::UnsupportedNode, "`for` loops can only iterate over ranges", range.span).into()),
+    };
+    //Counting in i128 so that ranges ending at the largest value of their type do not overflow
+    let t = Some(start.1.or(end.1).unwrap_or(IntType::I32));
+    let lo = start.0;
+    let hi = if inclusive {end.0 + 1} else {end.0};
+    let mut i = if reversed {hi - 1} else {lo};
+
+    vars.add_scope();
+    let mut result = Ok(Value::Unit);
+    'iterations: while lo <= i && i < hi{
+        vars.clear_scope();
+        vars.insert(var, &Value::Int(i, t));
+        for n in instr{
+            match interpret(n, vars, funcs){
+                Ok(_) => (),
+                Err(ControlFlow::Continue(l)) if targets(&l, label) => break,
+                Err(ControlFlow::Break(l, _v)) if targets(&l, label) => break 'iterations,
+                Err(e) => {
+                    result = Err(e);
+                    break 'iterations;
+                },
             }
-            vars.remove_scope();
         }
-        return Ok(Value::NoValue);
+        i = if reversed {i - 1} else {i + 1};
+    }
+    vars.remove_scope();
+    result
 }
 
-pub fn interpret_if_else(condition: &Node, if_instr: &Vec<Box<Node>>, else_instr: &Vec<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext)
-    -> Result<Value, &'static str>{
-        let if_condition = interpret(condition, vars, funcs);
-        let condition_value = match if_condition.unwrap(){
-            Value::Boolean(b) => b,
-            _ => panic!("while condition did not evaluate to boolean"),
-        };
+pub fn interpret_break(label: &Option<String>, value: &Option<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let value = match value{
+        Some(v) => interpret(v, vars, funcs)?,
+        None => Value::Unit,
+    };
+    Err(ControlFlow::Break(label.clone(), value))
+}
+
+pub fn interpret_if(condition: &Node, instr: &[Box<Node>], vars: &mut VarContext, funcs: &mut FnContext)-> Result<Value, ControlFlow>{
+    let if_condition = interpret(condition, vars, funcs)?;
+        let condition_value = expect_boolean(&if_condition, condition.span)?;
         if condition_value{
-            vars.add_scope();
-            for n in if_instr{
-                let _r = interpret(n, vars, funcs);
-            }
-            vars.remove_scope();
+            interpret_block(instr, vars, funcs)?;
         }
-        else{
-            vars.add_scope();
-            for n in else_instr{
-                let _r = interpret(n, vars, funcs);
-            }
-            vars.remove_scope();
+        Ok(Value::Unit)
+}
+
+pub fn interpret_if_else(condition: &Node, if_instr: &[Box<Node>], else_instr: &[Box<Node>], vars: &mut VarContext, funcs: &mut FnContext)
+    -> Result<Value, ControlFlow>{
+        let if_condition = interpret(condition, vars, funcs)?;
+        let condition_value = expect_boolean(&if_condition, condition.span)?;
+        if condition_value{
+            return interpret_block(if_instr, vars, funcs);
         }
-        return Ok(Value::NoValue); 
+        interpret_block(else_instr, vars, funcs)
     }
 
-pub fn interpret_return(node: &Option<Box<Node>>, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-    let ret = match node{
-        Some(v) => interpret(&v, vars, funcs),
-        None => Ok(Value::NoValue),
+pub fn interpret_return(node: &Option<Box<Node>>, span: Span, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let value = match node{
+        Some(v) => interpret_as(v, funcs.ret_type, vars, funcs)?,
+        None => Value::Unit,
     };
-    ret
-}
-
-pub fn interpret(node: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, &'static str>{
-    let val = match node{
-        Node::Number(n) => Ok(Value::Number(*n)),
-        Node::Boolean(b) => Ok(Value::Boolean(*b)),
-        Node::ID(s) => Ok(vars.get(s).unwrap().value),
-        Node::Op(n1, o, n2) => interpret_op(n1, o, n2, vars, funcs),
-        Node::UnaryOp(o, n) => interpret_unary_op(o, n, vars, funcs),
-        Node::Declaration(name, _b, _typedef, value) 
-        => interpret_let(name, value, vars, funcs),
-        Node::Assign(s, n) => interpret_assign(s, n, vars, funcs),
-        Node::While(n, v) => interpret_while(n, v, vars, funcs),
-        Node::IfStmt(n, v) => interpret_if(n, v, vars, funcs),
-        Node::IfElse(n, v1, v2) => interpret_if_else(n, v1, v2, vars, funcs),
-        Node::BlockValue(a) => interpret(a, vars, funcs),
-        Node::Return(o) => interpret_return(o, vars, funcs),
-        Node::Call(s, v) => interpret_call_wrapper(s, v, funcs, vars),
-        Node::WriteByRef(_o, s, v) => interpret_write_ref(s, v, vars, funcs),
-        Node::Program(v) => interpret_program(v),
-        _ => panic!("err"), 
+    let span = match node{
+        Some(v) => v.span,
+        None => span,
+    };
+    Err(ControlFlow::Return(value, span))
+}
+
+#[allow(clippy::needless_return)]
+pub fn interpret(node: &Node, vars: &mut VarContext, funcs: &mut FnContext) -> Result<Value, ControlFlow>{
+    let val = match &node.kind{
+        NodeKind::Number(n, t) => Ok(Value::Int(*n as i128, *t)),
+        NodeKind::Float(x) => Ok(Value::Float(*x)),
+        NodeKind::Str(s) => Ok(Value::Str(s.clone())),
+        NodeKind::Char(c) => Ok(Value::Char(*c)),
+        NodeKind::MethodCall(n, m, _args) => interpret_method_call(n, m, node.span, vars, funcs),
+        NodeKind::Index(n, i) => interpret_index(n, i, node.span, vars, funcs),
+        NodeKind::Cast(n, t) => interpret_cast(n, t, vars, funcs),
+        NodeKind::Boolean(b) => Ok(Value::Boolean(*b)),
+        NodeKind::Unit => Ok(Value::Unit),
+        NodeKind::ID(s) => Ok(lookup(vars, s, node.span)?.value),
+        NodeKind::Op(n1, o, n2) => interpret_op(n1, o, n2, vars, funcs),
+        NodeKind::UnaryOp(o, n) => interpret_unary_op(o, n, node.span, vars, funcs),
+        NodeKind::Declaration(name, _b, typedef, value) 
+        => interpret_let(name, typedef, value, vars, funcs),
+        NodeKind::Assign(s, n) => interpret_assign(s, n, node.span, vars, funcs),
+        NodeKind::While(l, n, v) => interpret_while(l, n, v, vars, funcs),
+        NodeKind::Loop(l, v) => interpret_loop(l, v, vars, funcs),
+        NodeKind::For(l, var, range, v) => interpret_for(l, var, range, v, vars, funcs),
+        NodeKind::Break(l, v) => interpret_break(l, v, vars, funcs),
+        NodeKind::Continue(l) => Err(ControlFlow::Continue(l.clone())),
+        NodeKind::IfStmt(n, v) => interpret_if(n, v, vars, funcs),
+        NodeKind::IfElse(n, v1, v2) => interpret_if_else(n, v1, v2, vars, funcs),
+        NodeKind::Block(v) => interpret_block(v, vars, funcs),
+        NodeKind::BlockValue(a) => interpret(a, vars, funcs),
+        NodeKind::Return(o) => interpret_return(o, node.span, vars, funcs),
+        NodeKind::Call(s, v) => interpret_call_wrapper(s, v, node.span, funcs, vars),
+        NodeKind::WriteByRef(_o, s, v) => interpret_write_ref(s, v, node.span, vars, funcs),
+        NodeKind::Program(v) => interpret_program_with_builtins(v, &funcs.options.clone(), &funcs.builtins.clone(), &mut funcs.out).map_err(ControlFlow::Error),
+        NodeKind::Print(newline, f, v) => interpret_print(*newline, f, v, node.span, vars, funcs),
+        _ => Err(Diagnostic::error(ErrorCode::UnsupportedNode, "this syntax cannot be evaluated", node.span).into()), 
     };
     return val;
 
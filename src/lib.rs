@@ -0,0 +1,30 @@
+use lalrpop_util::lalrpop_mod;
+
+lalrpop_mod!(#[allow(clippy::all)] pub parser, "/parser.rs");
+
+pub mod span;
+pub mod comments;
+pub mod literals;
+pub mod builtins;
+pub mod errors;
+pub mod ast;
+pub mod typechecker;
+pub mod interpreter;
+pub mod emitter;
+
+use crate::ast::Node;
+use crate::comments::strip_comments;
+use crate::errors::{Diagnostic, parse_error, place_after_expression};
+use crate::parser::ProgramParser;
+
+//Parses a whole program, translating syntax errors into diagnostics
+pub fn parse_program(source: &str) -> Result<Box<Node>, Vec<Diagnostic>> {
+    let source = strip_comments(source).map_err(|e| vec![e])?;
+    let program = ProgramParser::new().parse(&source).map_err(|e| vec![place_after_expression(&source, parse_error(&source, e))])?;
+    let mut errors = Vec::new();
+    program.syntax_errors(&mut errors);
+    if !errors.is_empty() {
+        return Err(errors.into_iter().map(|e| place_after_expression(&source, e)).collect());
+    }
+    Ok(program)
+}
@@ -0,0 +1,136 @@
+use crate::errors::{Diagnostic, ErrorCode};
+use crate::span::Span;
+
+//The text of a string literal, `raw` is the literal without its quotes starting at offset `lo`
+pub fn unescape_str(raw: &str, lo: usize) -> Result<String, Diagnostic> {
+    let mut out = String::new();
+    let mut chars = raw.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        //A `\` at the end of a line skips the line break and the indentation after it
+        if let Some((_, '\n')) = chars.peek() {
+            while let Some((_, c)) = chars.peek() {
+                if !c.is_whitespace() {
+                    break;
+                }
+                chars.next();
+            }
+            continue;
+        }
+        let (c, len) = escape(&raw[i..], lo + i)?;
+        out.push(c);
+        for _ in 1..len {
+            chars.next();
+        }
+    }
+    Ok(out)
+}
+
+//The character of a char literal, `raw` is the literal without its quotes starting at offset `lo`
+pub fn unescape_char(raw: &str, lo: usize) -> Result<char, Diagnostic> {
+    if raw.starts_with('\\') {
+        return escape(raw, lo).map(|(c, _)| c);
+    }
+    //The lexer only accepts a single character between the quotes
+    Ok(raw.chars().next().unwrap_or_default())
+}
+
+//The character an escape sequence at the start of `s` stands for, and the number of
+//characters it takes up
+fn escape(s: &str, lo: usize) -> Result<(char, usize), Diagnostic> {
+    let mut chars = s.chars();
+    chars.next();
+    let c = match chars.next() {
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('t') => '\t',
+        Some('0') => '\0',
+        Some('\\') => '\\',
+        Some('\'') => '\'',
+        Some('"') => '"',
+        Some('x') => {
+            let digits: String = chars.take(2).collect();
+            return match u8::from_str_radix(&digits, 16) {
+                Ok(b) if digits.len() == 2 && b <= 0x7f => Ok((b as char, 4)),
+                _ => Err(Diagnostic::error(ErrorCode::InvalidLiteral, "invalid `\\x` escape", Span::new(lo, lo + 2 + digits.len()))
+                    .with_note("`\\x` takes two hexadecimal digits up to `7f`")),
+            };
+        },
+        Some('u') => {
+            let rest = chars.as_str();
+            let end = match rest.find('}') {
+                Some(end) if rest.starts_with('{') => end,
+                _ => return Err(Diagnostic::error(ErrorCode::InvalidLiteral, "incorrect unicode escape", Span::new(lo, lo + 2))
+                    .with_note("unicode escapes are written like `\\u{1F600}`")),
+            };
+            let span = Span::new(lo, lo + 3 + end);
+            return match u32::from_str_radix(&rest[1..end], 16).ok().and_then(char::from_u32) {
+                Some(c) => Ok((c, 3 + end)),
+                None => Err(Diagnostic::error(ErrorCode::InvalidLiteral, "invalid unicode character escape", span)),
+            };
+        },
+        other => {
+            let len = other.map_or(1, |c| 1 + c.len_utf8());
+            return Err(Diagnostic::error(ErrorCode::InvalidLiteral, "unknown character escape", Span::new(lo, lo + len))
+                .with_note("the escapes are `\\n`, `\\r`, `\\t`, `\\0`, `\\\\`, `\\'`, `\\\"`, `\\x7f` and `\\u{7fff}`"));
+        },
+    };
+    Ok((c, 2))
+}
+
+//A part of the format string of `print!` and `println!`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatPiece {
+    Text(String),
+    //`{}`, the next argument shown with Display
+    Display,
+    //`{:?}`, the next argument shown with Debug
+    Debug,
+}
+
+//Splits a format string into text and placeholders, `{{` and `}}` being escaped braces
+pub fn format_pieces(format: &str) -> Result<Vec<FormatPiece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            },
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("expected `}` but the format string ended".to_string()),
+                    }
+                }
+                let piece = match spec.as_str() {
+                    "" => FormatPiece::Display,
+                    ":?" => FormatPiece::Debug,
+                    _ => return Err(format!("unsupported placeholder `{{{}}}`, only `{{}}` and `{{:?}}` can be used", spec)),
+                };
+                if !text.is_empty() {
+                    pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(piece);
+            },
+            '}' => return Err("unmatched `}` in the format string, write `}}` for a `}`".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    Ok(pieces)
+}
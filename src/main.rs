@@ -1,55 +1,194 @@
-use lalrpop_util::lalrpop_mod;
+use compiler1::parse_program;
+use compiler1::ast::NodeKind;
+use compiler1::errors::{Diagnostic, ErrorCode};
+use compiler1::span::SourceFile;
+use compiler1::typechecker::type_check_program;
+use compiler1::interpreter::{interpret_program_with, Options, OverflowMode, Value};
+use compiler1::emitter::{ColorChoice, ErrorFormat, Emitter};
+use std::io::{self, Read};
+use std::{env, fs, process};
 
-lalrpop_mod!(pub parser, "/parser.rs");
+const USAGE: &str = "usage: comp <parse|check|run> [OPTIONS] [FILE]
 
-use parser::*;
+Commands:
+    parse    parse FILE and print the syntax tree
+    check    parse and type check FILE
+    run      parse, type check and interpret FILE, printing the value returned by main
 
-pub mod ast;
-pub mod typechecker;
-pub mod interpreter;
+Options:
+    --color=<auto|always|never>    when to color diagnostics (default auto)
+    --error-format=<human|json>    print diagnostics for people or as one JSON object per line
+    --overflow=<trap|wrap>         stop with an error on integer overflow or wrap around (default trap)
 
-use crate::ast::{Opcode, Node};
-use crate::typechecker::{type_check_op, init_context, init_funcs, type_check};
-use crate::interpreter::{interpret, interp_context, interp_fn_context};
-use std::{env, fs};
+FILE may be `-` or omitted to read the program from standard input.";
 
+enum Command {
+    Parse,
+    Check,
+    Run,
+}
 
 fn main(){
-    test_parse();
-    test_types();
-    test_interp();
-    let mut dir = env::current_dir().unwrap();
-    println!("{:?}", dir);
-    dir.pop();
-    dir.pop();
-    println!("{:?}", dir);
-    dir.push("src");
-    dir.push("tests");
-    dir.push("mut_borrow_test.txt");
-    let s = fs::read_to_string(dir).unwrap();
-    let parse = ProgramParser::new().parse(&s);
-    if parse.is_err(){
-        println!("Parsing of the input program failed");
-        return;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first().map(|s| s.as_str()) {
+        Some("parse") => Command::Parse,
+        Some("check") => Command::Check,
+        Some("run") => Command::Run,
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(other) => usage_error(&format!("unknown command `{}`", other)),
+        None => usage_error("no command given"),
+    };
+
+    let mut color = ColorChoice::Auto;
+    let mut format = ErrorFormat::Human;
+    let mut options = Options::default();
+    let mut path = None;
+    for arg in &args[1..] {
+        if let Some(value) = arg.strip_prefix("--color=") {
+            color = match ColorChoice::parse(value) {
+                Some(c) => c,
+                None => usage_error(&format!("invalid value `{}` for --color", value)),
+            };
+        }
+        else if let Some(value) = arg.strip_prefix("--error-format=") {
+            format = match ErrorFormat::parse(value) {
+                Some(f) => f,
+                None => usage_error(&format!("invalid value `{}` for --error-format", value)),
+            };
+        }
+        else if let Some(value) = arg.strip_prefix("--overflow=") {
+            options.overflow = match value {
+                "trap" => OverflowMode::Trap,
+                "wrap" => OverflowMode::Wrap,
+                _ => usage_error(&format!("invalid value `{}` for --overflow", value)),
+            };
+        }
+        else if arg.starts_with("--") {
+            usage_error(&format!("unknown option `{}`", arg));
+        }
+        else if path.is_none() {
+            path = Some(arg.as_str());
+        }
+        else {
+            usage_error("expected at most one input file");
+        }
     }
-    let mut c = init_context();
-    let mut f = init_funcs();
-    let type_check_result = type_check(&parse.clone().unwrap(), &mut c, &mut f);
-    println!("type_checker result: {:?}", type_check_result);
-
-    if type_check_result.is_ok(){
-        let mut c = interp_context();
-        let mut f = interp_fn_context();
-        println!("interpreter result: {:?}", interpret(&parse.unwrap(), &mut c, &mut f));
+
+    let path = path.unwrap_or("-");
+    let source = match read_source(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("error: could not read `{}`: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let file = SourceFile::new(path, &source);
+    if let Err(diagnostics) = execute(&command, &file, &options) {
+        let emitter = match format {
+            ErrorFormat::Human => Emitter::new(color),
+            ErrorFormat::Json => Emitter::json(),
+        };
+        for diagnostic in &diagnostics {
+            eprintln!("{}", emitter.render(&file, diagnostic));
+        }
+        process::exit(1);
     }
+}
 
+fn usage_error(msg: &str) -> ! {
+    eprintln!("error: {}\n\n{}", msg, USAGE);
+    process::exit(2);
+}
 
+//Reads the program from the given path, "-" meaning standard input
+fn read_source(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut s = String::new();
+        io::stdin().read_to_string(&mut s)?;
+        return Ok(s);
+    }
+    fs::read_to_string(path)
 }
 
+fn execute(command: &Command, file: &SourceFile, options: &Options) -> Result<(), Vec<Diagnostic>> {
+    let program = parse_program(&file.src)?;
+    let functions = match &program.kind {
+        NodeKind::Program(v) => v,
+        _ => return Err(vec![Diagnostic::error(ErrorCode::InvalidProgram, "expected a program", program.span)]),
+    };
+
+    if let Command::Parse = command {
+        println!("{:#?}", program);
+        return Ok(());
+    }
+
+    type_check_program(functions)?;
+    if let Command::Check = command {
+        return Ok(());
+    }
 
+    match interpret_program_with(functions, options).map_err(|e| vec![e])? {
+        Value::Unit | Value::NoValue => (),
+        v => println!("{}", v),
+    };
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+use super::*;
+use compiler1::parser::*;
+use compiler1::ast::{IntType, Node, Opcode, TypeExpr};
+use compiler1::span::{Span, LineCol};
+use compiler1::typechecker::{type_check_op, init_context, init_funcs, type_check, type_check_program_with, Types};
+use compiler1::interpreter::{interpret, interp_context, interp_fn_context, interpret_program, interpret_program_to, interpret_program_with_builtins};
+use compiler1::builtins::prelude;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn node(kind: NodeKind) -> Node {
+    Node::new(kind, Span::default())
+}
+
+//The functions of a program that must parse
+#[allow(clippy::vec_box)]
+fn functions(src: &str) -> Vec<Box<Node>> {
+    match parse_program(src).unwrap().kind {
+        NodeKind::Program(v) => v,
+        _ => panic!("not a program"),
+    }
+}
+
+//Runs a program that must type check, giving the value returned by main
+fn run(src: &str) -> Result<String, Diagnostic> {
+    let v = functions(src);
+    assert!(type_check_program(&v).is_ok(), "{} failed to type check", src);
+    interpret_program(&v).map(|v| format!("{}", v))
+}
+
+//Runs a program that must type check and finish, giving what it printed
+fn output(src: &str) -> String {
+    let v = functions(src);
+    assert!(type_check_program(&v).is_ok(), "{} failed to type check", src);
+    let mut out = Vec::new();
+    interpret_program_to(&v, &Options::default(), &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+//The codes of the type errors in a program
+fn check(src: &str) -> Vec<ErrorCode> {
+    type_check_program(&functions(src)).unwrap_err().iter().map(|e| e.code).collect()
+}
+
+#[test]
 fn test_parse() {
     assert!(NumParser::new().parse("1").is_ok());
-    assert!(NumParser::new().parse("1").unwrap() == 1);
+    assert!(NumParser::new().parse("1").unwrap() == (1, None));
     assert!(IdParser::new().parse("hello").is_ok());
     assert!(IdParser::new().parse("hello").unwrap() == "hello");
     assert!(TermParser::new().parse("(123)").is_ok());
@@ -140,17 +279,18 @@ fn test_parse() {
 
 
 
+  #[test]
   fn test_types(){
     let mut c = init_context();
     let mut funcs = init_funcs();
-    assert!(type_check_op(&Node::Number(14), &Opcode::Add, &Node::Number(13), &mut c, &mut funcs).is_ok());
-    let n1 = Node::Op(Box::new(Node::Number(13)), Opcode::Add, Box::new(Node::Number(13)));
-    let n2 = Node::Op(Box::new(Node::Number(15)), Opcode::Sub, Box::new(Node::Number(14)));
+    assert!(type_check_op(&node(NodeKind::Number(14, None)), &Opcode::Add, &node(NodeKind::Number(13, None)), &mut c, &mut funcs).is_ok());
+    let n1 = node(NodeKind::Op(Box::new(node(NodeKind::Number(13, None))), Opcode::Add, Box::new(node(NodeKind::Number(13, None)))));
+    let n2 = node(NodeKind::Op(Box::new(node(NodeKind::Number(15, None))), Opcode::Sub, Box::new(node(NodeKind::Number(14, None)))));
     assert!(type_check_op(&n1, &Opcode::Add, &n2, &mut c, &mut funcs).is_ok());
-    assert!(type_check_op(&n1, &Opcode::Mod, &Node::ID("A".to_string()), &mut c, &mut funcs).is_ok());
-    assert!(type_check_op(&n1, &Opcode::Mod, &Node::ID("D".to_string()), &mut c, &mut funcs).is_err());
-    let n3 = Box::new(Node::Op(Box::new(Node::Number(13)), Opcode::Neq, Box::new(Node::Number(56))));
-    let n4 = Node::Op(n3, Opcode::And, Box::new(Node::Boolean(true)));
+    assert!(type_check_op(&n1, &Opcode::Mod, &node(NodeKind::ID("A".to_string())), &mut c, &mut funcs).is_ok());
+    assert!(type_check_op(&n1, &Opcode::Mod, &node(NodeKind::ID("D".to_string())), &mut c, &mut funcs).is_err());
+    let n3 = Box::new(node(NodeKind::Op(Box::new(node(NodeKind::Number(13, None))), Opcode::Neq, Box::new(node(NodeKind::Number(56, None))))));
+    let n4 = node(NodeKind::Op(n3, Opcode::And, Box::new(node(NodeKind::Boolean(true)))));
     assert!(type_check(&n4, &mut c, &mut funcs).is_ok());
     assert!(type_check_op(&n1, &Opcode::And, &n2, &mut c, &mut funcs).is_err());
     assert!(type_check(&BoolExpParser::new().parse("D == false && 14 < 17 || A >= B").unwrap(), &mut c, &mut funcs).is_ok());
@@ -193,7 +333,8 @@ fn test_parse() {
         let x = 1+4;
         let y = true || false;
         let z = 2345;
-        let a: i32 = foo(1 + 4, true || false, z + 1);
+        assert(true || false);
+        let a: i32 = max(1 + 4, z + 1);
         a
     }").unwrap(), &mut c, &mut funcs).is_ok());
     assert!(type_check(&FunctionParser::new().parse("fib(a: bool, d: bool) -> i32 {
@@ -241,6 +382,7 @@ fn test_parse() {
     ").unwrap(), &mut c, &mut funcs).is_ok());
   }
 
+  #[test]
   fn test_interp(){
       let mut c = interp_context();
       let mut funcs = interp_fn_context();
@@ -268,4 +410,718 @@ fn test_parse() {
     }").unwrap(), &mut c, &mut funcs);*/
     
       
-  }
\ No newline at end of file
+  }
+
+  #[test]
+  fn test_spans(){
+      let src = "fn main() -> i32 {\n    let x = 1 + 2;\n    x\n}\n";
+      let file = SourceFile::new("main.rs", src);
+      let program = ProgramParser::new().parse(src).unwrap();
+      let f = match &program.kind {
+          NodeKind::Program(v) => v[0].clone(),
+          _ => panic!("not a program"),
+      };
+      assert_eq!(file.snippet(f.span), &src[3..src.len() - 1]);
+      let body = match &f.kind {
+          NodeKind::FnDef(_, _, _, b, _) => b.clone(),
+          _ => panic!("not a function"),
+      };
+      assert_eq!(file.snippet(body[0].span), "let x = 1 + 2");
+      assert_eq!(file.line_col(body[0].span.lo), LineCol {line: 2, col: 5});
+      let value = match &body[0].kind {
+          NodeKind::Declaration(_, _, _, Some(v)) => v.clone(),
+          _ => panic!("not a declaration"),
+      };
+      assert_eq!(file.snippet(value.span), "1 + 2");
+      assert_eq!(file.line_col(value.span.hi), LineCol {line: 2, col: 18});
+      assert_eq!(file.snippet(body[1].span), "x");
+      assert_eq!(file.line_col(body[1].span.lo), LineCol {line: 3, col: 5});
+      assert_eq!(file.line(3), "    x");
+      assert_eq!(file.line_count(), 5);
+  }
+
+  #[test]
+  fn test_diagnostics(){
+      let first_error = |src: &str| type_check_program(&functions(src)).unwrap_err().remove(0);
+      let e = first_error("fn main() {\n  let x: i32 = true;\n}");
+      assert_eq!(e.code, ErrorCode::MismatchedTypes);
+      assert_eq!(e.code.code(), "E0201");
+      assert!(matches!(e.expected.as_deref(), Some(Types::Int(IntType::I32))));
+      assert!(matches!(e.found.as_deref(), Some(Types::Boolean)));
+      assert_eq!(e.span, Some(Span::new(27, 31)));
+      assert_eq!(first_error("fn main() { foo(1); }").code, ErrorCode::UndefinedFunction);
+      assert_eq!(first_error("fn main() { let x = 5; x = 6; }").code, ErrorCode::AssignToImmutable);
+      //The cause of an error inside an operand is kept
+      let e = first_error("fn main() -> i32 { 1 + (2 + true) }");
+      assert_eq!(e.code, ErrorCode::InvalidOperands);
+      assert_eq!(e.span, Some(Span::new(28, 32)));
+      let e = parse_program("fn main() { let x = ; }").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::UnexpectedToken);
+      assert_eq!(e.span, Some(Span::new(20, 21)));
+  }
+
+  #[test]
+  fn test_error_recovery(){
+      //Independent errors in different functions and statements are all reported, in source order
+      let codes = check("fn main() {\n  let a: i32 = true;\n  a = 2;\n}\nfn foo() -> bool {\n  bar(1);\n  5\n}");
+      assert_eq!(codes, vec![ErrorCode::MismatchedTypes, ErrorCode::AssignToImmutable, ErrorCode::UndefinedFunction, ErrorCode::ReturnTypeMismatch]);
+      //Uses of a variable whose initializer was invalid do not cause further errors
+      assert_eq!(check("fn main() {\n  let x = 1 + true;\n  let y = x + 1;\n  let z: bool = x;\n}"), vec![ErrorCode::InvalidOperands]);
+      //Errors inside blocks and both operands of an operation are reported
+      assert_eq!(check("fn main() {\n  while 1 { let a: bool = 2; };\n  if true { (1 + true) == (false + 2) }\n}").len(), 4);
+  }
+
+  //Deterministic pseudo random choices for generating programs
+  struct Gen {
+      state: u64,
+  }
+
+  impl Gen {
+      fn pick(&mut self, n: usize) -> usize {
+          self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+          ((self.state >> 33) as usize) % n
+      }
+
+      fn choose<'a>(&mut self, options: &[&'a str]) -> &'a str {
+          options[self.pick(options.len())]
+      }
+
+      fn expr(&mut self, depth: usize) -> String {
+          if depth == 0 {
+              return self.choose(&["1", "0", "true", "false", "x", "y", "z", "A"]).to_string();
+          }
+          match self.pick(6) {
+              0 => format!("{} {} {}", self.expr(depth - 1), self.choose(&["+", "-", "*", "/", "%", "<", "<=", "==", "!=", "&&", "||"]), self.expr(depth - 1)),
+              1 => format!("{}{}", self.choose(&["-", "!", "&", "&mut ", "*"]), self.expr(depth - 1)),
+              2 => format!("({})", self.expr(depth - 1)),
+              3 => {
+                  let args: Vec<String> = (0..self.pick(4)).map(|_| self.expr(depth - 1)).collect();
+                  format!("{}({})", self.choose(&["f", "g", "main", "h"]), args.join(", "))
+              }
+              _ => self.expr(0),
+          }
+      }
+
+      fn instr(&mut self, depth: usize) -> String {
+          let id = self.choose(&["x", "y", "z"]);
+          match self.pick(9) {
+              0 => format!("let {}{}{} = {}", self.choose(&["", "mut "]), id, self.choose(&["", ": i32", ": bool", ": &i32", ": &mut bool"]), self.expr(depth)),
+              1 => format!("let {}{}", self.choose(&["", "mut "]), id),
+              2 => format!("{} = {}", id, self.expr(depth)),
+              3 => format!("*{} = {}", id, self.expr(depth)),
+              4 if depth > 0 => format!("if {} {{ {} }}", self.expr(depth - 1), self.body(depth - 1)),
+              5 if depth > 0 => format!("if {} {{ {} }} else {{ {} }}", self.expr(depth - 1), self.body(depth - 1), self.body(depth - 1)),
+              6 if depth > 0 => format!("while {} {{ {} }}", self.expr(depth - 1), self.body(depth - 1)),
+              7 => format!("return {}", self.expr(depth)),
+              _ => self.expr(depth),
+          }
+      }
+
+      fn body(&mut self, depth: usize) -> String {
+          let instrs: Vec<String> = (0..self.pick(4)).map(|_| self.instr(depth)).collect();
+          instrs.join("; ")
+      }
+
+      fn program(&mut self) -> String {
+          let mut src = String::new();
+          for _ in 0..self.pick(3) + 1 {
+              let params: Vec<String> = (0..self.pick(3)).map(|_| format!("{}{}", self.choose(&["x", "y", "z"]), self.choose(&[": i32", ": bool", ": &i32", ": &mut i32"]))).collect();
+              let ret = self.choose(&["", "-> i32", "-> bool", "-> &i32", "-> Foo", "-> ()"]);
+              src.push_str(&format!("fn {}({}) {} {{ {} }}\n", self.choose(&["f", "g", "main"]), params.join(", "), ret, self.body(3)));
+          }
+          src
+      }
+  }
+
+  #[test]
+  fn test_type_checker_never_panics(){
+      let mut gen = Gen {state: 7};
+      let mut parsed = 0;
+      for _ in 0..500 {
+          let src = gen.program();
+          if let Ok(program) = parse_program(&src) {
+              parsed += 1;
+              if let NodeKind::Program(v) = &program.kind {
+                  //Panics fail the test, the result itself does not matter
+                  let result = std::panic::catch_unwind(|| type_check_program(v));
+                  assert!(result.is_ok(), "type checking panicked on:\n{}", src);
+              }
+          }
+      }
+      assert!(parsed > 200, "only {} generated programs parsed", parsed);
+      //The specific cases that used to panic
+      assert_eq!(check("fn main() -> i32 { x }")[0], ErrorCode::UndefinedVariable);
+      assert_eq!(check("fn f(a: i32) -> i32 { a } fn main() -> i32 { f(1, 2) }")[0], ErrorCode::WrongArgumentCount);
+      assert_eq!(check("fn f(a: i32, b: bool) -> i32 { a } fn main() -> i32 { f(1) }")[0], ErrorCode::WrongArgumentCount);
+      assert_eq!(check("fn main() -> Foo { 1 }")[0], ErrorCode::UnknownType);
+      assert_eq!(check("fn main() { let a = 1; let b = &mut a; }")[0], ErrorCode::InvalidBorrow);
+      assert_eq!(check("fn main() { let a: &mut i32 = 1; }")[0], ErrorCode::MismatchedTypes);
+  }
+
+  #[test]
+  fn test_runtime_errors(){
+      //Some of these programs would be rejected by the type checker
+      let run_unchecked = |src: &str| interpret_program(&functions(src));
+      let e = run_unchecked("fn main() -> i32 {\n  let x = 0;\n  10 / x\n}").unwrap_err();
+      assert_eq!(e.code, ErrorCode::DivisionByZero);
+      assert_eq!(e.span, Some(Span::new(34, 40)));
+      assert_eq!(run_unchecked("fn main() -> i32 { let x = 0; 10 % x }").unwrap_err().code, ErrorCode::DivisionByZero);
+      assert_eq!(run_unchecked("fn main() -> i32 { 2147483647 + 1 }").unwrap_err().code, ErrorCode::IntegerOverflow);
+      assert_eq!(run_unchecked("fn main() -> i32 { let x = 0 - 2147483647 - 1; x / -1 }").unwrap_err().code, ErrorCode::IntegerOverflow);
+      //The type checker would reject these, the interpreter must not crash on them either
+      assert_eq!(run_unchecked("fn main() -> i32 { foo(1) }").unwrap_err().code, ErrorCode::UnknownFunction);
+      assert_eq!(run_unchecked("fn main() -> i32 { x }").unwrap_err().code, ErrorCode::UnboundVariable);
+      assert_eq!(run_unchecked("fn main() -> i32 { 1 + true }").unwrap_err().code, ErrorCode::RuntimeTypeError);
+      assert_eq!(run_unchecked("fn f(x: i32) -> i32 { x } fn main() -> i32 { f() }").unwrap_err().code, ErrorCode::ArgumentCount);
+      assert_eq!(run_unchecked("fn f() { }").unwrap_err().code, ErrorCode::UnknownFunction);
+      assert_eq!(run_unchecked("fn main() { let a = 1; *a = 2; }").unwrap_err().code, ErrorCode::InvalidReference);
+      assert_eq!(run_unchecked("fn main() -> u8 { let x: i32 = 300; x }").unwrap_err().code, ErrorCode::IntegerOverflow);
+      let e = run_unchecked("fn main() { let mut a = 2; let b = &mut a; let c = &a; let x = *b; }").unwrap_err();
+      assert_eq!(e.code, ErrorCode::BorrowConflict);
+      let e = run_unchecked("fn f(x: &mut i32, y: &mut i32) { } fn main() { let mut a = 1; let b = &mut a; f(b, b); }").unwrap_err();
+      assert_eq!(e.code, ErrorCode::BorrowConflict);
+      //Errors inside loops and branches are not ignored
+      let e = run_unchecked("fn main() { let mut i = 3; while true { i = i - 1; let x = 6 / i; }; }").unwrap_err();
+      assert_eq!(e.code, ErrorCode::DivisionByZero);
+      //Unbounded recursion is stopped before it overflows the stack
+      let e = run_unchecked("fn f(n: i32) -> i32 { f(n + 1) }\nfn main() -> i32 { f(0) }").unwrap_err();
+      assert_eq!(e.code, ErrorCode::RecursionLimit);
+      assert_eq!(e.span, Some(Span::new(22, 30)));
+      assert_eq!(format!("{}", run_unchecked("fn f(n: i32) -> i32 { if n == 0 { 0 } else { f(n - 1) + 1 } } fn main() -> i32 { f(1000) }").unwrap()), "1000");
+  }
+
+  #[test]
+  fn test_early_return(){
+      //A return inside a loop or branch leaves the function immediately
+      assert_eq!(run("fn find(limit: i32) -> i32 {
+          let mut i = 0;
+          while true {
+              if i * i > limit { return i; };
+              i = i + 1;
+          };
+          return 0
+      }
+      fn main() -> i32 { find(50) }").unwrap(), "8");
+      assert_eq!(run("fn sign(x: i32) -> i32 {
+          if x < 0 { return -1; } else { if x == 0 { return 0; }; };
+          return 1;
+          2
+      }
+      fn main() -> i32 { sign(-5) * 100 + sign(0) * 10 + sign(7) }").unwrap(), "-99");
+      //The returned expression is evaluated once
+      assert_eq!(run("fn inc(c: &mut i32) -> i32 { *c = *c + 1; return *c }
+      fn g(c: &mut i32) -> i32 { return inc(c) }
+      fn main() -> i32 { let mut n = 0; let r = &mut n; g(r); n }").unwrap(), "1");
+      //Branches that return have the type of the other branch
+      assert_eq!(run("fn pick(x: bool) -> i32 { if x { return 1; } else { return 2; } }
+      fn half(x: i32) -> i32 { let h = if x % 2 == 0 { x / 2 } else { return -1; }; h }
+      fn main() -> i32 { pick(false) * 100 + half(8) * 10 + half(3) }").unwrap(), "239");
+      //A nested return must have the declared type
+      assert_eq!(check("fn main() -> i32 { while true { return false; }; 1 }"), vec![ErrorCode::ReturnTypeMismatch]);
+  }
+
+  #[test]
+  fn test_loops(){
+      assert_eq!(run("fn main() -> i32 { let mut i = 0; loop { i = i + 1; if i == 5 { break; }; }; i }").unwrap(), "5");
+      assert_eq!(run("fn main() -> i32 { let mut i = 0; let x = loop { i = i + 1; if i * i > 30 { break i * 10; }; }; x }").unwrap(), "60");
+      //continue skips the rest of the body
+      assert_eq!(run("fn main() -> i32 { let mut i = 0; let mut n = 0; while i < 10 { i = i + 1; if i % 3 != 0 { continue; }; n = n + i; }; n }").unwrap(), "18");
+      //Labels pick the loop to leave or continue
+      assert_eq!(run("fn main() -> i32 {
+          let mut i = 0;
+          let mut sum = 0;
+          let found = 'outer: loop {
+              i = i + 1;
+              let mut j = 0;
+              while j < 10 {
+                  j = j + 1;
+                  if j % 2 == 0 { continue; };
+                  if i * j > 40 { break 'outer i * 100 + j; };
+                  if j > i { continue 'outer; };
+                  sum = sum + j;
+              };
+          };
+          found + sum
+      }").unwrap(), "635");
+      //A break inside a called function does not leave the caller's loop
+      assert_eq!(run("fn f() -> i32 { loop { break 1; } } fn main() -> i32 { let mut n = 0; while n < 3 { n = n + f(); }; n }").unwrap(), "3");
+      //A loop without a break never finishes, so it can be used as a value of any type
+      assert_eq!(run("fn f(n: i32) -> i32 { let mut i = 0; loop { i = i + 1; if i * i >= n { return i; }; } } fn main() -> i32 { f(50) }").unwrap(), "8");
+      assert_eq!(run("fn f() -> i32 { let x = return 1; -x + 1 } fn main() -> i32 { f() }").unwrap(), "1");
+
+      assert_eq!(check("fn main() { break; }"), vec![ErrorCode::BreakOutsideLoop]);
+      assert_eq!(check("fn main() { if true { continue; }; }"), vec![ErrorCode::BreakOutsideLoop]);
+      assert_eq!(check("fn main() { loop { break 'a; }; }"), vec![ErrorCode::UndeclaredLabel]);
+      assert_eq!(check("fn main() { while true { break 1; }; }"), vec![ErrorCode::BreakWithValue]);
+      assert_eq!(check("fn main() { let x = loop { break 1; break true; }; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let x: bool = loop { break 1; }; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn f() -> i32 { loop { break; } } fn main() { }"), vec![ErrorCode::ReturnTypeMismatch]);
+  }
+
+  #[test]
+  fn test_for_loops(){
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in 0..5 { s = s * 10 + i; }; s }").unwrap(), "1234");
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in 1..=3 { s = s * 10 + i; }; s }").unwrap(), "123");
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in (1..=3).rev() { s = s * 10 + i; }; s }").unwrap(), "321");
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in (0..3).rev() { s = s * 10 + i; }; s }").unwrap(), "210");
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in 5..2 { s = 1; }; s }").unwrap(), "0");
+      //The bounds are evaluated once and ranges can end at i32::MAX
+      assert_eq!(run("fn main() -> i32 { let mut n = 3; for i in 0..n { n = n + 1; }; n }").unwrap(), "6");
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in 2147483646..=2147483647 { s = s + 1; }; s }").unwrap(), "2");
+      assert_eq!(run("fn main() -> i32 {
+          let mut s = 0;
+          'a: for i in 0..100 {
+              if i == 3 { break 'a; };
+              for j in 0..=1 { if j == 1 { continue 'a; }; s = s + 1; };
+          };
+          s
+      }").unwrap(), "3");
+
+      assert_eq!(check("fn main() { for i in 0..3 { i = 2; }; }"), vec![ErrorCode::AssignToImmutable]);
+      assert_eq!(check("fn main() { for i in 0..true { }; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { for i in 0..3 { }; let x = i; }"), vec![ErrorCode::UndefinedVariable]);
+      let e = parse_program("fn main() { for i in (0..3).skip() { }; }").unwrap_err().remove(0);
+      assert_eq!(e.span, Some(Span::new(28, 32)));
+      let e = type_check_program(&functions("fn main() -> i32 { 2147483648 }")).unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::LiteralOutOfRange);
+      assert_eq!(e.span, Some(Span::new(19, 29)));
+      let e = parse_program("fn main() -> u64 { 18446744073709551616 }").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::InvalidLiteral);
+      assert_eq!(e.span, Some(Span::new(19, 39)));
+  }
+
+  #[test]
+  fn test_else_if(){
+      let sign = "fn sign(n: i32) -> i32 { if n < 0 { -1 } else if n == 0 { 0 } else { 1 } }";
+      assert_eq!(run(&format!("{} fn main() -> i32 {{ sign(-7) * 100 + sign(0) * 10 + sign(3) }}", sign)).unwrap(), "-99");
+      assert_eq!(run("fn main() -> i32 {
+          let mut s = 0;
+          for i in 0..4 {
+              if i == 0 { s = s + 1; } else if i == 1 { s = s + 10; } else if i == 2 { s = s + 100; };
+          };
+          s
+      }").unwrap(), "111");
+      assert_eq!(run("fn main() -> i32 { let x = if false { 1 } else if false { 2 } else { 3 }; x }").unwrap(), "3");
+
+      //Every arm of a chain used as a value has to agree
+      assert_eq!(check("fn main() -> i32 { if true { 1 } else if false { true } else { 3 } }"), vec![ErrorCode::MismatchedTypes]);
+  }
+
+  #[test]
+  fn test_block_statements(){
+      //Block-like instructions need no semicolon before the next instruction
+      assert_eq!(run("fn main() -> i32 {
+          let mut s = 0;
+          while s < 3 { s = s + 1; }
+          if s == 3 { s = s * 10; } else { s = 0; }
+          for i in 0..2 { s = s + i; }
+          { let t = 5; s = s + t; }
+          s
+      }").unwrap(), "36");
+      assert_eq!(run("fn main() -> i32 { let x = 5; { let y = 2; x + y } }").unwrap(), "7");
+      assert_eq!(run("fn main() -> i32 { let x = { 4 }; if x > 3 { x } else { 0 } }").unwrap(), "4");
+      assert_eq!(run("fn foo() { if true { let x = false; } return; } fn main() -> i32 { foo(); 1 }").unwrap(), "1");
+      assert!(parse_program("fn main() { let x = 1 let y = 2; }").is_err());
+
+      //Only a trailing expression gives a block its value
+      assert_eq!(check("fn main() -> i32 { if true { 1 } }"), vec![ErrorCode::ReturnTypeMismatch]);
+      assert_eq!(check("fn main() -> i32 { let mut x = 1; while x < 3 { x = x + 1; } }"), vec![ErrorCode::ReturnTypeMismatch]);
+      assert_eq!(check("fn main() -> i32 { let mut x = 1; x = 2 }"), vec![ErrorCode::ReturnTypeMismatch]);
+  }
+
+  #[test]
+  fn test_comments(){
+      let src = "/// Adds one\n/// to `x`\nfn inc(x: i32) -> i32 { x + 1 // trailing\n}\n\n/* outer /* nested */ still a comment */\n//// not a doc comment\nfn main() -> i32 { /* inline */ inc(1) }";
+      let functions = functions(src);
+      match &functions[0].kind {
+          NodeKind::FnDef(name, _, _, _, doc) => {
+              assert_eq!(name, "inc");
+              assert_eq!(doc.as_deref(), Some("Adds one\nto `x`"));
+          }
+          _ => panic!("not a function"),
+      }
+      match &functions[1].kind {
+          NodeKind::FnDef(_, _, _, _, doc) => assert!(doc.is_none()),
+          _ => panic!("not a function"),
+      }
+      assert_eq!(format!("{}", interpret_program(&functions).unwrap()), "2");
+
+      //Spans still point into the original source
+      let e = parse_program("/* a */ fn main() { let x = ; }").unwrap_err().remove(0);
+      assert_eq!(e.span, Some(Span::new(28, 29)));
+      //A `/*` inside a line comment does not start a block comment
+      assert!(parse_program("fn main() { // see /* here\n}").is_ok());
+
+      let e = parse_program("fn main() { /* /* */ }").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::UnterminatedComment);
+      assert_eq!(e.span, Some(Span::new(12, 14)));
+      //Comment markers inside string and char literals are part of the literal
+      assert!(parse_program("fn main() { let s = \"// /* not a comment\"; let c = '/'; let d = '\\''; 'a: loop { break 'a; } }").is_ok());
+      //Inside a function a doc comment is an ordinary comment, unless nothing follows it
+      assert!(parse_program("fn main() -> i32 {\n  /// the start\n  let x = 1;\n  /// the result\n  x\n}").is_ok());
+      let e = parse_program("fn main() {\n  let x = 1;\n  /// nothing to document\n}").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::UnexpectedToken);
+      assert_eq!(e.message, "found a documentation comment that doesn't document anything");
+  }
+
+  #[test]
+  fn test_type_syntax(){
+      let ty = |src: &str| match TypeParser::new().parse(src).unwrap().kind {
+          NodeKind::Type(t) => t,
+          _ => panic!("not a type"),
+      };
+      let i32_ty = || Box::new(TypeExpr::Named("i32".to_string()));
+      assert_eq!(ty("i32"), TypeExpr::Named("i32".to_string()));
+      assert_eq!(ty("()"), TypeExpr::Unit);
+      assert_eq!(ty("& mut i32"), TypeExpr::MutRef(i32_ty()));
+      assert_eq!(ty("&mut i32"), TypeExpr::MutRef(i32_ty()));
+      assert_eq!(ty("&&i32"), TypeExpr::Ref(Box::new(TypeExpr::Ref(i32_ty()))));
+      assert_eq!(ty("&mut &i32"), TypeExpr::MutRef(Box::new(TypeExpr::Ref(i32_ty()))));
+      assert_eq!(format!("{}", ty("&mut  &  bool")), "&mut &bool");
+
+      //Whitespace around the `:` and in the type does not matter
+      let src = "fn get(x:&i32, y :  & mut bool) -> i32 { *y = true; *x }
+          fn main() -> i32 { let a: i32 = 5; let mut b = false; let ra: &i32 = &a; let rb = &mut b; let c :i32 = get(ra, rb); c }";
+      assert_eq!(run(src).unwrap(), "5");
+      assert!(type_check_program(&functions("fn id(x: &i32) -> i32 { *x } fn main() -> () { }")).is_ok());
+      let e = type_check_program(&functions("fn main() { let x: &mut i33 = 1; }")).unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::UnknownType);
+      assert_eq!(e.message, "cannot find type `i33`");
+      assert_eq!(e.span, Some(Span::new(19, 27)));
+      //A parameter of an unknown type is reported once and its function body is still checked
+      assert_eq!(check("fn f(x: foo) { let y: i32 = true; } fn main() { }"), vec![ErrorCode::UnknownType, ErrorCode::MismatchedTypes]);
+  }
+
+  #[test]
+  fn test_structural_types(){
+      let messages = |src: &str| match type_check_program(&functions(src)) {
+          Ok(_) => Vec::new(),
+          Err(errors) => errors.iter().map(|e| e.to_string()).collect::<Vec<String>>(),
+      };
+      assert_eq!(messages("fn f(x: &i32) -> i32 { *x } fn main() { let b = true; let r = &b; f(r); }"),
+          vec!["error[E0201]: mismatched types in argument 1 of `f` (expected `&i32`, found `&bool`)"]);
+      assert_eq!(run("fn main() -> i32 { let a = 5; let b = &a; let c: &&i32 = &b; let d: i32 = **c; d }").unwrap(), "5");
+      assert_eq!(run("fn f(x: &&i32, y: &mut &i32, z: &mut i32) -> i32 { *z = 2; **x + **y } fn main() -> i32 { let a = 5; let b = &a; let c = &b; let mut d = &a; let e = &mut d; let mut g = 0; let h = &mut g; f(c, e, h) + g }").unwrap(), "12");
+      assert_eq!(messages("fn main() { let a = 5; let b = &a; let c: &i32 = &b; }"),
+          vec!["error[E0201]: mismatched types (expected `&i32`, found `&&i32`)"]);
+      assert_eq!(messages("fn main() { let a = 5; let mut b = &a; let c: &mut &bool = &mut b; }"),
+          vec!["error[E0201]: mismatched types (expected `&mut &bool`, found `&mut &i32`)"]);
+      assert_eq!(messages("fn f(x: &mut i32) {} fn main() { let mut a = 5; let r = &a; f(r); }"),
+          vec!["error[E0201]: mismatched types in argument 1 of `f` (expected `&mut i32`, found `&i32`)"]);
+
+      assert_eq!(Types::Ref(Box::new(Types::Ref(Box::new(Types::Int(IntType::I32))))), Types::Ref(Box::new(Types::Ref(Box::new(Types::Int(IntType::I32))))));
+      assert_ne!(Types::Ref(Box::new(Types::Int(IntType::I32))), Types::Ref(Box::new(Types::Boolean)));
+      assert_eq!(Types::MutRef(Box::new(Types::Ref(Box::new(Types::Boolean)))).to_string(), "&mut &bool");
+      assert_eq!(Types::UnitType.to_string(), "()");
+  }
+
+  #[test]
+  fn test_unit(){
+      assert_eq!(run("fn nothing(u: ()) -> () { u } fn main() -> bool { let x: () = (); let y = nothing(x); y == () }").unwrap(), "true");
+      assert_eq!(run("fn main() -> bool { let x = { let a = 1; }; x != () }").unwrap(), "false");
+      assert_eq!(run("fn main() -> () { return (); }").unwrap(), "()");
+      assert_eq!(run("fn main() { let mut n = 0; n = 1 }").unwrap(), "()");
+
+      assert_eq!(check("fn main() { let x: () = 5; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() -> bool { () < () }"), vec![ErrorCode::InvalidOperands]);
+      assert_eq!(check("fn main() -> bool { () == 1 }"), vec![ErrorCode::MismatchedTypes]);
+  }
+
+  #[test]
+  fn test_integer_types(){
+      assert_eq!(run("fn main() -> u8 { let x: u8 = 250; x + 5 }").unwrap(), "255");
+      assert_eq!(run("fn main() -> u8 { let x = 250u8; x + 6 }").unwrap_err().code, ErrorCode::IntegerOverflow);
+      assert_eq!(run("fn main() -> i64 { let x: i64 = 3000000000; x * 3 }").unwrap(), "9000000000");
+      assert_eq!(run("fn main() -> u64 { 18446744073709551615 }").unwrap(), "18446744073709551615");
+      assert_eq!(run("fn main() -> i8 { -128i8 }").unwrap(), "-128");
+      assert_eq!(run("fn main() -> i32 { let x = 2147483647; x + 1 }").unwrap_err().code, ErrorCode::IntegerOverflow);
+      assert_eq!(run("fn f(x: u16) -> u16 { x * 2 } fn main() -> u16 { f(40000) }").unwrap_err().code, ErrorCode::IntegerOverflow);
+      assert_eq!(run("fn main() -> u8 { 300 as u8 }").unwrap(), "44");
+      assert_eq!(run("fn main() -> u8 { -1i32 as u8 }").unwrap(), "255");
+      assert_eq!(run("fn main() -> i8 { 200u8 as i8 }").unwrap(), "-56");
+      assert_eq!(run("fn main() -> i64 { -5i8 as i64 }").unwrap(), "-5");
+      assert_eq!(run("fn main() -> u32 { true as u32 + 1 }").unwrap(), "2");
+      assert_eq!(run("fn main() -> u64 { let mut s: u64 = 0; for i in 0..3u64 { s = s + i; } s }").unwrap(), "3");
+
+      assert_eq!(check("fn main() { let a: u8 = 1; let b: i32 = 2; let c = a + b; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let a = 1; let b: i64 = a; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let a: u8 = 256; }"), vec![ErrorCode::LiteralOutOfRange]);
+      assert_eq!(check("fn main() { let a = 128i8; }"), vec![ErrorCode::LiteralOutOfRange]);
+      assert_eq!(check("fn main() { let a: u32 = 1; let b = -a; }"), vec![ErrorCode::InvalidOperands]);
+      assert_eq!(check("fn main() { let a = 1 as bool; }"), vec![ErrorCode::InvalidCast]);
+      assert_eq!(check("fn main() { let a = () as i32; }"), vec![ErrorCode::InvalidCast]);
+  }
+
+  #[test]
+  fn test_floats(){
+      assert_eq!(run("fn main() -> f64 { 1.5 + 2.25 }").unwrap(), "3.75");
+      assert_eq!(run("fn main() -> f64 { 2e3 / 8.0 }").unwrap(), "250");
+      assert_eq!(run("fn main() -> f64 { let x: f64 = 1f64; -x * 0.5 }").unwrap(), "-0.5");
+      assert_eq!(run("fn main() -> bool { 0.1 + 0.2 != 0.3 }").unwrap(), "true");
+      assert_eq!(run("fn main() -> f64 { 1.0 / 0.0 }").unwrap(), "inf");
+      assert_eq!(run("fn main() -> i32 { let mut s = 0; for i in 0..3 { s = s + i; } s }").unwrap(), "3");
+      assert_eq!(run("fn main() -> f64 { 7 as f64 / 2.0 }").unwrap(), "3.5");
+      assert_eq!(run("fn main() -> i32 { -3.9 as i32 }").unwrap(), "-3");
+      assert_eq!(run("fn main() -> u8 { 300.5 as u8 }").unwrap(), "255");
+      assert_eq!(run("fn main() -> u8 { -1.0 as u8 }").unwrap(), "0");
+      assert_eq!(run("fn main() -> i32 { (0.0 / 0.0) as i32 }").unwrap(), "0");
+
+      assert_eq!(check("fn main() { let x = 1.0 + 1; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let x: i32 = 2; let y = x < 1.5; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let x: f64 = 1; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let x = 1.5 as bool; }"), vec![ErrorCode::InvalidCast]);
+      let e = parse_program("fn main() { let x = 1e999; }").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::InvalidLiteral);
+  }
+
+  #[test]
+  fn test_strings(){
+      assert_eq!(run("fn main() -> String { let s = \"Hello\"; s + \", \" + \"world\" }").unwrap(), "Hello, world");
+      assert_eq!(run("fn greet(name: &str) -> String { \"hi \" + name } fn main() -> String { greet(\"bob\") }").unwrap(), "hi bob");
+      assert_eq!(run("fn main() -> &str { \"a\\tb\\n\\\"c\\\"\\\\\" }").unwrap(), "a\tb\n\"c\"\\");
+      assert_eq!(run("fn main() -> &str { \"\\u{e9}\\x41\" }").unwrap(), "\u{e9}A");
+      assert_eq!(run("fn main() -> &str { \"one \\\n    two\" }").unwrap(), "one two");
+      assert_eq!(run("fn main() -> usize { \"h\\u{e9}llo\".len() }").unwrap(), "6");
+      assert_eq!(run("fn main() -> u8 { let s = \"abc\"; s[1] }").unwrap(), "98");
+      assert_eq!(run("fn main() -> u8 { let s = \"abc\"; s[3] }").unwrap_err().code, ErrorCode::IndexOutOfBounds);
+      assert_eq!(run("fn main() -> bool { let a = \"x\" + \"y\"; a == \"xy\" && \"a\" != \"b\" }").unwrap(), "true");
+      assert_eq!(run("fn main() -> char { '\\n' }").unwrap(), "\n");
+      assert_eq!(run("fn main() -> bool { 'a' < 'b' && '\\'' == '\\u{27}' }").unwrap(), "true");
+      assert_eq!(run("fn main() -> u32 { '\\u{e9}' as u32 }").unwrap(), "233");
+      assert_eq!(run("fn main() -> char { (b() + 1) as char } fn b() -> u8 { 'a' as u8 }").unwrap(), "b");
+      //A `String`, or a reference to one, can be passed for a `&str`
+      assert_eq!(run("fn len(s: &str) -> usize { s.len() } fn main() -> usize { let s = \"ab\" + \"c\"; let r = &s; len(s) + len(&s) + len(r) }").unwrap(), "9");
+      assert_eq!(run("fn main() { panic(\"bad \" + \"value\"); }").unwrap_err().code, ErrorCode::Panic);
+
+      assert_eq!(check("fn main() { let s = \"a\" + 1; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let s: String = \"a\"; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn f(s: &String) { } fn main() { f(\"a\"); }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let b = \"a\" < \"b\"; }"), vec![ErrorCode::InvalidOperands]);
+      assert_eq!(check("fn main() { let n = \"a\".size(); }"), vec![ErrorCode::UnknownMethod]);
+      assert_eq!(check("fn main() { let i: i32 = 0; let b = \"a\"[i]; }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let c = 97 as char; }"), vec![ErrorCode::InvalidCast]);
+      let e = parse_program("fn main() { let s = \"\\q\"; }").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::InvalidLiteral);
+      assert_eq!(e.span, Some(Span::new(21, 23)));
+  }
+
+  #[test]
+  fn test_print(){
+      assert_eq!(output("fn main() { let x = 5; println!(\"x = {}\", x); print!(\"{} {}\", true, 1.5); println!(); }"), "x = 5\ntrue 1.5\n");
+      assert_eq!(output("fn main() { println!(\"{:?} {:?} {:?} {}\", \"a\\\"b\", 'c', 2.0, 'd'); }"), "\"a\\\"b\" 'c' 2.0 d\n");
+      assert_eq!(output("fn main() { println!(\"{{}} {:?}\", ()); }"), "{} ()\n");
+      assert_eq!(output("fn main() { let a = 3; let r = &a; println!(\"{}\", r); }"), "3\n");
+      assert_eq!(output("fn main() { let mut a = 3; println!(\"{} {:?}\", &a, &mut a); }"), "3 3\n");
+      //References do not outlive a call, so they cannot be returned
+      assert_eq!(check("fn id(x: &i32) -> &i32 { x } fn main() -> i32 { let a = 4; let r = &a; let b = id(r); *b }"), vec![ErrorCode::InvalidBorrow]);
+      assert_eq!(output("fn f(n: i32) -> i32 { println!(\"f({})\", n); n } fn main() { let x = f(1) + f(2); }"), "f(1)\nf(2)\n");
+
+      assert_eq!(check("fn main() { println!(\"{} {}\", 1); }"), vec![ErrorCode::InvalidFormat]);
+      assert_eq!(check("fn main() { println!(\"{}\", 1, 2); }"), vec![ErrorCode::InvalidFormat]);
+      assert_eq!(check("fn main() { println!(\"{:x}\", 1); }"), vec![ErrorCode::InvalidFormat]);
+      assert_eq!(check("fn main() { println!(\"{\", 1); }"), vec![ErrorCode::InvalidFormat]);
+      assert_eq!(check("fn main() { println!(\"{}\", ()); }"), vec![ErrorCode::InvalidFormat]);
+      assert_eq!(check("fn main() { println!(\"{}\", y); }"), vec![ErrorCode::UndefinedVariable]);
+  }
+
+  #[test]
+  fn test_builtins(){
+      assert_eq!(run("fn main() -> i32 { abs(-4) + min(3, 9) * max(-1, 2) }").unwrap(), "10");
+      assert_eq!(run("fn main() -> i32 { pow(2, 10) }").unwrap(), "1024");
+      assert_eq!(run("fn main() { assert(1 < 2); assert_eq((\"a\" + \"b\").len(), 2); assert_eq(3u8, 3); }").unwrap(), "()");
+      //A function of the program hides the builtin
+      assert_eq!(run("fn max(a: i32, b: i32) -> i32 { a } fn main() -> i32 { max(1, 2) }").unwrap(), "1");
+
+      let e = run("fn main() -> i32 { pow(2, 31) }").unwrap_err();
+      assert_eq!(e.code, ErrorCode::IntegerOverflow);
+      assert_eq!(e.span, Some(Span::new(19, 29)));
+      let e = run("fn main() { assert_eq(1 + 1, 3); }").unwrap_err();
+      assert_eq!(e.code, ErrorCode::Panic);
+      assert_eq!(e.notes, vec!["left: 2, right: 3".to_string()]);
+      let e = run("fn main() { assert(false); }").unwrap_err();
+      assert_eq!(e.message, "assertion failed");
+      let e = run("fn main() { panic(\"oh no\"); }").unwrap_err();
+      assert_eq!((e.code, e.message.as_str()), (ErrorCode::Panic, "panicked: oh no"));
+
+      assert_eq!(check("fn main() { let x = abs(true); }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let x = min(1); }"), vec![ErrorCode::WrongArgumentCount]);
+      assert_eq!(check("fn main() { assert_eq(1, true); }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(check("fn main() { let x: i64 = 5; let y = pow(2, x); }"), vec![ErrorCode::MismatchedTypes]);
+  }
+
+  #[test]
+  fn test_host_functions(){
+      let log = Rc::new(RefCell::new(Vec::new()));
+      let mut builtins = prelude();
+      builtins.insert("double", vec![Types::Int(IntType::I64)], Types::Int(IntType::I64), |args| match args[0] {
+          Value::Int(n, _) => Ok(Value::Int(n * 2, Some(IntType::I64))),
+          _ => unreachable!(),
+      });
+      let sink = log.clone();
+      builtins.insert("log", vec![Types::Str], Types::UnitType, move |args| {
+          sink.borrow_mut().push(format!("{}", args[0]));
+          Ok(Value::Unit)
+      });
+      //Declared to return a bool but gives back an int
+      builtins.insert("broken", vec![], Types::Boolean, |_| Ok(Value::Int(1, None)));
+
+      let v = functions("fn main() -> i64 { log(\"start\"); let x: i64 = double(20); println!(\"{}\", x); log(\"end\"); x + double(1) }");
+      assert!(type_check_program_with(&v, &builtins).is_ok());
+      let mut out = Vec::new();
+      let result = interpret_program_with_builtins(&v, &Options::default(), &builtins, &mut out).unwrap();
+      assert_eq!(format!("{}", result), "42");
+      assert_eq!(String::from_utf8(out).unwrap(), "40\n");
+      assert_eq!(*log.borrow(), vec!["start".to_string(), "end".to_string()]);
+
+      let codes = |src: &str| type_check_program_with(&functions(src), &builtins).unwrap_err().iter().map(|e| e.code).collect::<Vec<ErrorCode>>();
+      assert_eq!(codes("fn main() { let x = double(true); }"), vec![ErrorCode::MismatchedTypes]);
+      assert_eq!(codes("fn main() { let x: i32 = double(1); }"), vec![ErrorCode::MismatchedTypes]);
+      //The host functions are only known to the programs they are given to
+      assert!(type_check_program(&functions("fn main() { log(\"hi\"); }")).unwrap_err().iter().any(|e| e.code == ErrorCode::UndefinedFunction));
+
+      let v = functions("fn main() -> bool { broken() }");
+      assert!(type_check_program_with(&v, &builtins).is_ok());
+      let e = interpret_program_with_builtins(&v, &Options::default(), &builtins, &mut Vec::new()).unwrap_err();
+      assert_eq!(e.code, ErrorCode::RuntimeTypeError);
+      assert_eq!(e.message, "`broken` returned `1`, but it is declared to return `bool`");
+  }
+
+  #[test]
+  fn test_overflow_modes(){
+      let run = |src: &str, overflow: OverflowMode| interpret_program_with(&functions(src), &Options {overflow});
+      let cases = [
+          ("2147483647 + 1", "-2147483648"),
+          ("0 - 2147483647 - 2", "2147483647"),
+          ("65536 * 65536", "0"),
+          ("m() / -1", "-2147483648"),
+          ("m() % -1", "0"),
+          ("-m()", "-2147483648"),
+      ];
+      for (expr, wrapped) in cases.iter() {
+          let src = format!("fn m() -> i32 {{ 0 - 2147483647 - 1 }} fn main() -> i32 {{ {} }}", expr);
+          let e = run(&src, OverflowMode::Trap).unwrap_err();
+          assert_eq!(e.code, ErrorCode::IntegerOverflow, "{}", expr);
+          assert_eq!(format!("{}", run(&src, OverflowMode::Wrap).unwrap()), *wrapped, "{}", expr);
+      }
+      //Division by zero is an error whatever the mode
+      assert_eq!(run("fn main() -> i32 { 1 / 0 }", OverflowMode::Wrap).unwrap_err().code, ErrorCode::DivisionByZero);
+      assert_eq!(format!("{}", run("fn main() -> i32 { 7 * 6 }", OverflowMode::Trap).unwrap()), "42");
+      //Writing through a reference follows the mode like a plain assignment
+      let src = "fn set(r: &mut u8) { *r = 200 + 100; } fn main() -> u8 { let mut x: u8 = 0; let r = &mut x; set(r); x }";
+      assert_eq!(format!("{}", run(src, OverflowMode::Wrap).unwrap()), "44");
+      assert_eq!(run(src, OverflowMode::Trap).unwrap_err().code, ErrorCode::IntegerOverflow);
+      //A returned value that does not fit is reported where it is returned
+      assert_eq!(run("fn main() -> i32 { 2147483647 + 1 }", OverflowMode::Trap).unwrap_err().span, Some(Span::new(19, 33)));
+      assert_eq!(run("fn main() -> i32 { return 2147483647 + 1; }", OverflowMode::Trap).unwrap_err().span, Some(Span::new(26, 40)));
+      //Arithmetic on untyped literals is done in the type its result gets, `i32` by default
+      for src in ["fn main() -> bool { 2147483647 + 1 > 0 }", "fn main() { let x = 2147483647 + 1 - 1; }"].iter() {
+          assert_eq!(run(src, OverflowMode::Trap).unwrap_err().code, ErrorCode::IntegerOverflow, "{}", src);
+      }
+      assert_eq!(format!("{}", run("fn main() -> bool { 2147483647 + 1 > 0 }", OverflowMode::Wrap).unwrap()), "false");
+      assert_eq!(format!("{}", run("fn main() -> i64 { let x: i64 = 3000000000 * 2; x }", OverflowMode::Trap).unwrap()), "6000000000");
+      assert_eq!(format!("{}", run("fn main() -> i64 { let x: i64 = 1; (2147483647 + 1) * x }", OverflowMode::Trap).unwrap()), "2147483648");
+      assert_eq!(format!("{}", run("fn f(x: u64) -> u64 { x } fn main() -> u64 { f(4294967296 * 2) }", OverflowMode::Trap).unwrap()), "8589934592");
+      //A variable declared without a value keeps its declared type
+      let src = "fn main() -> u8 { let mut x: u8; x = 200; x + 100 }";
+      assert_eq!(run(src, OverflowMode::Trap).unwrap_err().code, ErrorCode::IntegerOverflow);
+      assert_eq!(format!("{}", run(src, OverflowMode::Wrap).unwrap()), "44");
+      //The arithmetic builtins follow the mode too
+      for call in ["abs(0 - 2147483647 - 1)", "pow(2, 31)"].iter() {
+          let src = format!("fn main() -> i32 {{ {} }}", call);
+          assert_eq!(run(&src, OverflowMode::Trap).unwrap_err().code, ErrorCode::IntegerOverflow, "{}", call);
+          assert_eq!(format!("{}", run(&src, OverflowMode::Wrap).unwrap()), "-2147483648", "{}", call);
+      }
+  }
+
+  #[test]
+  fn test_parse_recovery(){
+      let errors = parse_program("fn main() {\n  let x = ;\n  let y = 5 +;\n  let z = 1\n}\nfn foo( { }\nfn bar() -> i32 { 1 }").unwrap_err();
+      let spans: Vec<Option<Span>> = errors.iter().map(|e| e.span).collect();
+      assert_eq!(spans, vec![Some(Span::new(22, 23)), Some(Span::new(37, 38)), Some(Span::new(61, 62))]);
+      assert!(errors.iter().all(|e| e.code == ErrorCode::UnexpectedToken));
+      assert_eq!(errors[2].message, "expected one of `)`, identifier, found `{`");
+      let e = parse_program("fn main() { 1 + 2").unwrap_err().remove(0);
+      assert_eq!(e.code, ErrorCode::UnexpectedEof);
+      assert!(e.message.ends_with("found end of file"), "{}", e.message);
+      assert!(parse_program("fn main() -> i32 { let x = 1; x }").is_ok());
+      //After an expression only the tokens closing its bracket are expected, right after it
+      let e = parse_program("fn main() {\n  let x = 1\n  let y = 2;\n}").unwrap_err().remove(0);
+      assert_eq!(e.message, "expected one of `;`, `}` after expression, found `let`");
+      assert_eq!(e.span, Some(Span::new(23, 23)));
+      let e = parse_program("fn main() { let x = f(1 2); }").unwrap_err().remove(0);
+      assert_eq!(e.message, "expected one of `)`, `,` after expression, found `2`");
+  }
+
+  #[test]
+  fn test_rendered_diagnostics(){
+      let file = SourceFile::new("test.rs", "fn main() {\n  let x: i32 = true;\n}");
+      let errors = execute(&Command::Check, &file, &Options::default()).unwrap_err();
+      let expected = "error[E0201]: mismatched types
+ --> test.rs:2:16
+  |
+2 |   let x: i32 = true;
+  |                ^^^^ expected `i32`, found `bool`
+  |   ----------------- `x` is declared with type `i32` here
+";
+      assert_eq!(Emitter::plain().render(&file, &errors[0]), expected);
+      let file = SourceFile::new("test.rs", "fn main() {\n  let x = 5;\n  x = 6;\n}");
+      let errors = execute(&Command::Check, &file, &Options::default()).unwrap_err();
+      let rendered = Emitter::plain().render(&file, &errors[0]);
+      assert!(rendered.ends_with("3 |   x = 6;\n  |   ^^^^^\n  = note: consider declaring it with `let mut x`\n"), "{}", rendered);
+      assert!(Emitter::new(ColorChoice::Always).render(&file, &errors[0]).contains("\x1b["));
+      assert!(!Emitter::new(ColorChoice::Never).render(&file, &errors[0]).contains("\x1b["));
+      //Tabs are expanded in the source line and in the padding before the markers
+      let file = SourceFile::new("test.rs", "fn main() {\n\tlet x: i32 = true;\n}");
+      let errors = execute(&Command::Check, &file, &Options::default()).unwrap_err();
+      let rendered = Emitter::plain().render(&file, &errors[0]);
+      assert!(rendered.contains("2 |     let x: i32 = true;\n  |                  ^^^^ "), "{}", rendered);
+      //An invalid token covers the whole character, however many bytes it takes
+      let file = SourceFile::new("test.rs", "fn main() { let x = \u{e9}; }");
+      let errors = execute(&Command::Check, &file, &Options::default()).unwrap_err();
+      assert_eq!(errors[0].span, Some(Span::new(20, 22)));
+      let rendered = Emitter::plain().render(&file, &errors[0]);
+      assert!(rendered.ends_with("1 | fn main() { let x = \u{e9}; }\n  |                     ^\n"), "{}", rendered);
+      assert!(Emitter::json().render(&file, &errors[0]).contains("\"column_start\":21,\"line_end\":1,\"column_end\":22"));
+      assert_eq!(file.line_col(21), LineCol {line: 1, col: 21});
+  }
+
+  #[test]
+  fn test_json_diagnostics(){
+      let file = SourceFile::new("dir/test \"1\".rs", "fn main() {\n  let x: i32 = true;\n}");
+      let errors = execute(&Command::Check, &file, &Options::default()).unwrap_err();
+      let json = Emitter::json().render(&file, &errors[0]);
+      assert!(!json.contains('\n'));
+      assert!(json.starts_with("{\"code\":\"E0201\",\"severity\":\"error\",\"message\":\"mismatched types\",\"expected\":\"i32\",\"found\":\"bool\",\"spans\":["));
+      assert!(json.contains("{\"file\":\"dir/test \\\"1\\\".rs\",\"byte_start\":27,\"byte_end\":31,\"line_start\":2,\"column_start\":16,\"line_end\":2,\"column_end\":20,\"is_primary\":true,\"label\":null}"), "{}", json);
+      assert!(json.contains("\"is_primary\":false,\"label\":\"`x` is declared with type `i32` here\"}"));
+      assert!(json.ends_with("\"notes\":[]}"));
+      let file = SourceFile::new("test.rs", "fn main() { let x = ; }");
+      let errors = execute(&Command::Parse, &file, &Options::default()).unwrap_err();
+      let json = Emitter::json().render(&file, &errors[0]);
+      assert!(json.starts_with("{\"code\":\"E0103\""));
+      assert!(json.contains("\"message\":\"expected one of "));
+  }
+
+  #[test]
+  fn test_example_programs(){
+      let expected = [
+          ("args.txt", "17"),
+          ("arithmetic.txt", "120"),
+          ("mut_borrow_test.txt", "45"),
+          ("sample.txt", "100"),
+          ("square.txt", "25"),
+          ("test1.txt", "()"),
+          ("while.txt", "15"),
+          ("for.txt", "30"),
+      ];
+      for (file, value) in expected.iter(){
+          let s = fs::read_to_string(format!("src/tests/{}", file)).unwrap();
+          let functions = functions(&s);
+          assert!(type_check_program(&functions).is_ok(), "{} failed to type check", file);
+          assert_eq!(format!("{}", interpret_program(&functions).unwrap()), *value, "{}", file);
+      }
+      assert!(execute(&Command::Check, &SourceFile::new("test", "fn main() { let x: i32 = true; }"), &Options::default()).is_err());
+      assert!(execute(&Command::Check, &SourceFile::new("test", "fn main() { let x = ; }"), &Options::default()).is_err());
+      assert!(execute(&Command::Run, &SourceFile::new("test", "fn main() -> i32 { 1 + 2 }"), &Options::default()).is_ok());
+  }
+}
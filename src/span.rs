@@ -0,0 +1,92 @@
+use std::cmp;
+
+//Byte offsets into the source text, `lo` inclusive and `hi` exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize) -> Span {
+        Span {lo, hi}
+    }
+
+    //The smallest span covering both self and other
+    pub fn to(&self, other: Span) -> Span {
+        Span {lo: cmp::min(self.lo, other.lo), hi: cmp::max(self.hi, other.hi)}
+    }
+
+    pub fn len(&self) -> usize {
+        self.hi - self.lo
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hi == self.lo
+    }
+}
+
+//A 1-based line and column, the column counted in characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize,
+}
+
+//A source file together with the byte offset of the start of every line
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub src: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, src: &str) -> SourceFile {
+        let mut line_starts = vec![0];
+        for (i, b) in src.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        SourceFile {name: name.to_string(), src: src.to_string(), line_starts}
+    }
+
+    //Zero-based index of the line containing the offset
+    fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+    }
+
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        //An offset inside a character counts as the start of that character
+        let mut offset = cmp::min(offset, self.src.len());
+        while !self.src.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_index(offset);
+        let start = self.line_starts[line];
+        let col = self.src[start..offset].chars().count();
+        LineCol {line: line + 1, col: col + 1}
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    //The text of a 1-based line without its line terminator
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(next) => *next,
+            None => self.src.len(),
+        };
+        self.src[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    pub fn snippet(&self, span: Span) -> &str {
+        &self.src[span.lo..span.hi]
+    }
+}
@@ -1,32 +1,129 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
-use crate::ast::Node;
-use crate::ast::Opcode;
+use std::fmt;
+use crate::ast::{Node, NodeKind};
+use crate::ast::{IntType, Opcode, TypeExpr};
+use crate::builtins::{prelude, Builtins};
+use crate::errors::{Diagnostic, ErrorCode};
+use crate::literals::{format_pieces, FormatPiece};
+use crate::span::Span;
 
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Types{
     Boolean,
-    Number,
+    Int(IntType),
+    //An unsuffixed integer literal, which takes the integer type of where it is used
+    IntLiteral,
+    Float,
+    Char,
+    //A string literal or a borrowed string, `&str`
+    Str,
+    //A string built at runtime, like the result of a concatenation
+    String,
     UnitType,
     Unknown,
     Ref(Box<Types>),
     MutRef(Box<Types>),
+    //The type of code that never finishes, like `return` or a `loop` without a `break`,
+    //it is compatible with every type since no value of it is ever produced
+    Never,
+    //The type of an expression that failed to type check, it is compatible with everything
+    //so that a single mistake does not cause a cascade of errors
+    Error,
 }
 
 
 impl Types{
-    fn get_type_id(&self) -> i32{
-        let id = match self {
-            Types::Unknown => 0,
-            Types::Boolean => 1,
-            Types::Number => 2,
-            Types::UnitType => 3,
-            Types::Ref(_a) => 4,
-            Types::MutRef(_b) => 5,
-        };
-        id
+    fn is_error(&self) -> bool {
+        matches!(self, Types::Error)
+    }
+
+    fn is_unknown(&self) -> bool {
+        matches!(self, Types::Unknown)
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Types::Int(_) | Types::IntLiteral)
+    }
+
+    fn is_numeric(&self) -> bool {
+        self.is_integer() || *self == Types::Float
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self, Types::Str | Types::String)
+    }
+
+    //Whether values of the type can be printed with `{}`
+    fn is_displayable(&self) -> bool {
+        match self {
+            Types::UnitType | Types::Unknown => false,
+            Types::Ref(t) | Types::MutRef(t) => t.is_displayable(),
+            _ => true,
+        }
+    }
+
+    //Structural equality, except that an error anywhere in either type matches anything
+    //and that a literal matches every integer type
+    fn compatible(&self, other: &Types) -> bool {
+        match (self, other) {
+            (Types::Error, _) | (_, Types::Error) => true,
+            (Types::Never, _) | (_, Types::Never) => true,
+            (Types::Int(_), Types::IntLiteral) | (Types::IntLiteral, Types::Int(_)) => true,
+            (Types::Ref(a), Types::Ref(b)) | (Types::MutRef(a), Types::MutRef(b)) => a.compatible(b),
+            (a, b) => a == b,
+        }
+    }
+
+    //Whether a value of this type can be passed where `expected` is wanted, a `String` or a
+    //reference to one being borrowed as a `&str`
+    fn coerces_to(&self, expected: &Types) -> bool {
+        match (self, expected) {
+            (Types::String, Types::Str) => true,
+            (Types::Ref(t), Types::Str) | (Types::MutRef(t), Types::Str) => t.is_text(),
+            (t, expected) => t.compatible(expected),
+        }
+    }
+
+    //The integer type of an operation on both types, a literal taking the type of the other operand
+    fn unify_int(&self, other: &Types) -> Option<Types> {
+        match (self, other) {
+            (Types::Int(a), Types::Int(b)) if a == b => Some(Types::Int(*a)),
+            (Types::Int(a), Types::IntLiteral) | (Types::IntLiteral, Types::Int(a)) => Some(Types::Int(*a)),
+            (Types::IntLiteral, Types::IntLiteral) => Some(Types::IntLiteral),
+            _ => None,
+        }
+    }
+
+    //Literals stored without a type annotation default to i32
+    fn defaulted(self) -> Types {
+        match self {
+            Types::IntLiteral => Types::Int(IntType::I32),
+            t => t,
+        }
+    }
+}
+
+//Types are shown the way they are written in a program
+impl fmt::Display for Types{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Types::Boolean => write!(f, "bool"),
+            Types::Int(t) => write!(f, "{}", t),
+            Types::IntLiteral => write!(f, "{{integer}}"),
+            Types::Float => write!(f, "f64"),
+            Types::Char => write!(f, "char"),
+            Types::Str => write!(f, "&str"),
+            Types::String => write!(f, "String"),
+            Types::UnitType => write!(f, "()"),
+            Types::Unknown => write!(f, "_"),
+            Types::Ref(t) => write!(f, "&{}", t),
+            Types::MutRef(t) => write!(f, "&mut {}", t),
+            Types::Never => write!(f, "!"),
+            Types::Error => write!(f, "{{type error}}"),
+        }
     }
 }
 
@@ -37,9 +134,24 @@ pub struct FnContext{
 
 pub struct Context{
     var_env: VecDeque<HashMap<String, VarInfo>>,
+    //Errors that were recovered from while checking the current function
+    pub errors: Vec<Diagnostic>,
+    //The declared return type of the function being checked, Unknown outside of functions
+    ret_type: Types,
+    //The loops enclosing the code being checked, innermost last
+    loops: Vec<LoopInfo>,
+}
+
+struct LoopInfo{
+    label: Option<String>,
+    //Only `loop` can be exited with a value
+    is_loop: bool,
+    //The type of the values given to `break`, decided by the first one
+    break_type: Option<Types>,
 }
 
 impl Context{
+    #[allow(clippy::needless_return)]
     fn get(&self, id: &String) -> Option<VarInfo> {
         for i in &self.var_env {
             if i.contains_key(id){
@@ -49,6 +161,7 @@ impl Context{
         return None;
     }
 
+    #[allow(clippy::len_zero, clippy::ptr_arg)]
     fn insert(&mut self, id: &String, t: &Types, mutable: &bool) {
         let mut var_info = VarInfo {t: Types::Unknown, mutable: true};
         var_info.t = t.clone();
@@ -108,10 +221,10 @@ pub struct FnInfo{
 #[allow(non_snake_case)]
 pub fn init_context() -> Context{
     let v = VecDeque::new();
-    let A = VarInfo {t: Types::Number, mutable: true};
-    let B: VarInfo = VarInfo {t: Types::Number, mutable: false};
+    let A = VarInfo {t: Types::Int(IntType::I32), mutable: true};
+    let B: VarInfo = VarInfo {t: Types::Int(IntType::I32), mutable: false};
     let D: VarInfo = VarInfo {t: Types::Boolean, mutable: true};
-    let mut c: Context = Context {var_env: v};
+    let mut c: Context = Context {var_env: v, errors: Vec::new(), ret_type: Types::Unknown, loops: Vec::new()};
     c.insert(&"A".to_string(), &A.t, &A.mutable);
     c.insert(&"B".to_string(), &B.t, &B.mutable);
     c.insert(&"D".to_string(), &D.t, &D.mutable);
@@ -119,54 +232,58 @@ pub fn init_context() -> Context{
 }
 
 pub fn init_funcs() -> FnContext{
-    let m = HashMap::new();
-    let mut parameters = vec![Types::Unknown; 3];
-    parameters[0] = Types::Number;
-    parameters[1] = Types::Boolean;
-    parameters[2] = Types::Number;
-    let fn_info = FnInfo {ret: Types::Number, params: parameters};
-    let mut funcs = FnContext{fn_env: m};
-    funcs.fn_env.insert("foo".to_string(), fn_info);
-    return funcs;
-}
-
-pub fn type_check_program(functions: &Vec<Box<Node>>) -> Result<Types, &'static str>{
-    let map = HashMap::new();
-    let mut funcs = FnContext{fn_env: map};
+    builtin_funcs(&prelude())
+}
+
+//The signatures of the builtin functions, which the functions of a program are added to
+fn builtin_funcs(builtins: &Builtins) -> FnContext{
+    let mut funcs = FnContext{fn_env: HashMap::new()};
+    for (name, builtin) in builtins.iter(){
+        funcs.fn_env.insert(name.clone(), FnInfo {ret: builtin.ret.clone(), params: builtin.params.clone()});
+    }
+    funcs
+}
+
+pub fn type_check_program(functions: &Vec<Box<Node>>) -> Result<Types, Vec<Diagnostic>>{
+    type_check_program_with(functions, &prelude())
+}
+
+//Type checks a program that can call the given builtins, like the host functions of an application
+pub fn type_check_program_with(functions: &Vec<Box<Node>>, builtins: &Builtins) -> Result<Types, Vec<Diagnostic>>{
+    let mut funcs = builtin_funcs(builtins);
+    let mut errors = Vec::new();
     for f in functions{
         let v = Vec::new();
         let mut info = FnInfo{ret: Types::Unknown, params: v};
-        let name = match &**f{
-            Node::FnDef(s, _, _, _) => s,
-            _ => panic!("invalid program"),
-        };
-        let ret_type = match &**f{
-            Node::FnDef(_, _, o, _) => o,
-            _ => panic!("invalid program"),
-        };
-        let params = match &**f{
-            Node::FnDef(_, p, _, _) => p,
-            _ => panic!("invalid program"),
+        let (name, params, ret_type) = match &f.kind{
+            NodeKind::FnDef(s, p, o, _, _) => (s, p, o),
+            _ => {
+                errors.push(Diagnostic::error(ErrorCode::InvalidProgram, "expected a function definition", f.span));
+                continue;
+            }
         };
         if ret_type.is_none(){
             info.ret = Types::UnitType;
         }
         else{
-            info.ret = match &ret_type.as_ref().unwrap() as &str{
-                "i32" => Types::Number,
-                "bool" => Types::Boolean,
-                "()" => Types::UnitType,
-                "&i32" => Types::Ref(Box::new(Types::Number)),
-                "&bool" => Types::Ref(Box::new(Types::Boolean)),
-                _ => panic!("invalid fn definition"),
+            info.ret = match return_type(ret_type.as_ref().unwrap()){
+                Ok(t) => t,
+                Err(e) => {
+                    errors.push(e);
+                    Types::Error
+                }
             };
         }
 
         let mut parameter_defs = vec![Types::Unknown; params.len()];
-        let mut i = 0;
-        for param in params{
-            parameter_defs[i] = check_param(param).unwrap();
-            i += 1;
+        for (i, param) in params.iter().enumerate(){
+            parameter_defs[i] = match check_param(param){
+                Ok(t) => t,
+                Err(e) => {
+                    errors.push(e);
+                    Types::Error
+                }
+            };
         };
 
         info.params = parameter_defs;
@@ -175,33 +292,91 @@ pub fn type_check_program(functions: &Vec<Box<Node>>) -> Result<Types, &'static
 
     }
     for f in functions{
-        let result = match &**f{
-            Node::FnDef(name, p, o, i) => type_check_fn_def(name, p, o, i, &mut funcs),
-            _ => panic!("invalid program"),
-        };
-        if result.is_err(){
-            return result;
+        if let NodeKind::FnDef(name, p, o, i, _) = &f.kind{
+            if let Err(e) = type_check_fn_def(name, p, o, i, f.span, &mut funcs){
+                errors.extend(e);
+            }
         }
     }
-    return Ok(Types::UnitType);
+    if !errors.is_empty(){
+        errors.sort_by_key(|e| e.span);
+        return Err(errors);
+    }
+    Ok(Types::UnitType)
+}
+
+//Keeps the first error of a construct to be returned to the caller and records the rest in the context
+fn note_error(first: &mut Option<Diagnostic>, e: Diagnostic, context: &mut Context){
+    if first.is_none(){
+        *first = Some(e);
+    }
+    else{
+        context.errors.push(e);
+    }
 }
 
-pub fn type_check_fn_def(id: &String, params: &Vec<Box<Node>>, rtype: &Option<String>, 
-    instr: &Vec<Box<Node>>, mut funcs: &mut FnContext) 
-    -> Result<Types, &'static str> {
+//Checks a node, continuing with the error type if it was invalid
+fn check_or_recover(node: &Node, first: &mut Option<Diagnostic>, context: &mut Context, funcs: &mut FnContext) -> Types{
+    match type_check(node, context, funcs){
+        Ok(t) => t,
+        Err(e) => {
+            note_error(first, e, context);
+            Types::Error
+        }
+    }
+}
+
+//Checks every instruction of a block, returning the type of its value
+fn check_block(instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic>{
+    let mut first = None;
+    let mut block_type = Types::UnitType;
+    for n in instr{
+        let t = check_or_recover(n, &mut first, context, funcs);
+        //If the final instruction does not have a semicolon the type will be determined by this instruction,
+        //a block with an instruction that never finishes has no value either
+        if let NodeKind::BlockValue(_) = &n.kind{
+            block_type = t;
+        }
+        else if t == Types::Never{
+            block_type = t;
+        }
+    }
+    match first{
+        Some(e) => Err(e),
+        None => Ok(block_type),
+    }
+}
+
+#[allow(clippy::explicit_counter_loop, clippy::needless_late_init, clippy::needless_return)]
+pub fn type_check_fn_def(id: &String, params: &Vec<Box<Node>>, rtype: &Option<Box<Node>>, 
+    instr: &Vec<Box<Node>>, span: Span, funcs: &mut FnContext) 
+    -> Result<Types, Vec<Diagnostic>> {
         let v = VecDeque::new();
-        let mut context = Context{var_env: v};
+        let mut context = Context{var_env: v, errors: Vec::new(), ret_type: Types::Unknown, loops: Vec::new()};
+        let registered = match funcs.fn_env.get(id){
+            Some(info) => info.params.clone(),
+            None => Vec::new(),
+        };
+        let mut parameter_defs = vec![Types::Unknown; params.len()];
+        let mut i = 0;
         for p in params{
-            let name = match &**p{
-                Node::ParamDef(n, _) => n,
-                _ => panic!("invalid params"),
+            let name = match &p.kind{
+                NodeKind::ParamDef(n, _) => n,
+                _ => return Err(vec![Diagnostic::error(ErrorCode::InvalidProgram, "expected a parameter definition", p.span)]),
             };
-            let typ = match &**p{
-                Node::ParamDef(_, t) => type_check_param_def(t).unwrap(),
-                _ => panic!("invalid param"),
+            //A parameter of an unknown type has the error type in the body, the type itself
+            //has already been reported when the function was registered
+            parameter_defs[i] = match check_param(p){
+                Ok(t) => t,
+                Err(e) => {
+                    if registered.get(i) != Some(&Types::Error){
+                        context.errors.push(e);
+                    }
+                    Types::Error
+                }
             };
-            context.insert(name, &typ, &true);
-
+            context.insert(name, &parameter_defs[i], &true);
+            i += 1;
         }
         
         let ret_type: Types;
@@ -209,114 +384,168 @@ pub fn type_check_fn_def(id: &String, params: &Vec<Box<Node>>, rtype: &Option<St
             ret_type = Types::UnitType;
         }
         else{
-            ret_type = match &rtype.as_ref().unwrap() as &str{
-                "i32" => Types::Number,
-                "bool" => Types::Boolean,
-                "()" => Types::UnitType,
-                "&i32" => Types::Ref(Box::new(Types::Number)),
-                "&bool" => Types::Ref(Box::new(Types::Boolean)),
-                _ => panic!("invalid fn definition"),
+            //An unknown return type has already been reported when the function was registered
+            ret_type = match funcs.fn_env.get(id){
+                Some(info) if info.ret.is_error() => Types::Error,
+                _ => type_from_node(rtype.as_ref().unwrap()).map_err(|e| vec![e])?,
             };
         }
 
-        let mut parameter_defs = vec![Types::Unknown; params.len()];
-        let mut i = 0;
-        for param in params{
-            parameter_defs[i] = type_check(param, &mut context, &mut funcs).unwrap();
-            i += 1;
-        }
-
+        context.ret_type = ret_type.clone();
 
+        //Every returned value, and the value of the body, needs to be of the declared return type
+        let mut returns = false;
         for n in instr{
-            let e = type_check(n, &mut context, funcs);
-            if e.is_err() {
-                return e;
-            }
-        }
-
-        let mut real_ret_type = Ok(Types::UnitType);
-        //Setting the actual returnted type
-        for n in instr{
-            match &**n {
-                Node::Return(_o) => real_ret_type = type_check(n, &mut context, funcs),
-                Node::BlockValue(_v) => real_ret_type = type_check(n, &mut context, funcs),
-                _ => (),
+            let t = match type_check(n, &mut context, funcs){
+                Ok(t) => t,
+                Err(e) => {
+                    context.errors.push(e);
+                    Types::Error
+                }
             };
-        }
-
-        //Checks if any returns are of different type than the last
-        for n in instr{
-            let e = type_check(n, &mut context, funcs);
-            if e.is_err() {
-                return e;
-            }
-            match &**n {
-                Node::Return(_o) => if e.unwrap().get_type_id() != real_ret_type.clone().unwrap().get_type_id(){
-                    return Err("Function tries to return different types");
-                },
-                Node::BlockValue(_v) => if e.unwrap().get_type_id() != real_ret_type.clone().unwrap().get_type_id(){
-                    return Err("Function tries to return different types");
+            //Returned values are checked by type_check_return
+            match &n.kind{
+                NodeKind::Return(_) => returns = true,
+                _ if t == Types::Never => returns = true,
+                NodeKind::BlockValue(_) => {
+                    returns = true;
+                    if !t.compatible(&ret_type){
+                        context.errors.push(Diagnostic::error(ErrorCode::ReturnTypeMismatch, &format!("mismatched return type in function `{}`", id), n.span)
+                            .with_types(&ret_type, &t));
+                    }
+                    else if let Err(e) = check_literal_range(n, &ret_type){
+                        context.errors.push(e);
+                    }
                 },
                 _ => (),
             };
         }
 
-        
-        if ret_type.get_type_id() == real_ret_type.unwrap().get_type_id(){
-            let fn_info = FnInfo {ret: ret_type.clone(), params: parameter_defs};
-            funcs.fn_env.insert(id.clone(), fn_info);
-            return Ok(ret_type);
+        if !returns && !ret_type.compatible(&Types::UnitType){
+            context.errors.push(Diagnostic::error(ErrorCode::ReturnTypeMismatch, &format!("function `{}` does not return a value", id), span)
+                .with_types(&ret_type, &Types::UnitType));
         }
 
-        return Err("Mismatched return types");
+        if !context.errors.is_empty(){
+            return Err(context.errors);
+        }
+        let fn_info = FnInfo {ret: ret_type.clone(), params: parameter_defs};
+        funcs.fn_env.insert(id.clone(), fn_info);
+        return Ok(ret_type);
 }
 
-pub fn type_check_call(id: &String, params: &Vec<Box<Node>>, mut context: &mut Context, mut funcs: &mut FnContext) -> Result<Types, &'static str>{
-    let fn_info = funcs.fn_env.get(id);
-    if fn_info.is_none(){
-        return Err("Function called not in context");
-    }
+//Turns the errors of a nested function or program into a single error, recording the rest
+fn first_error(mut errors: Vec<Diagnostic>, context: &mut Context) -> Diagnostic{
+    let first = errors.remove(0);
+    context.errors.extend(errors);
+    first
+}
+
+#[allow(clippy::explicit_counter_loop)]
+pub fn type_check_call(id: &String, params: &Vec<Box<Node>>, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic>{
+    let mut first = None;
     let mut parameter_defs = vec![Types::Unknown; params.len()];
     let mut i = 0;
     for param in params{
-        parameter_defs[i] = type_check(param, &mut context, &mut funcs).unwrap();
+        parameter_defs[i] = check_or_recover(param, &mut first, context, funcs);
         i += 1;
     }
-    let new_fn_info = funcs.fn_env.get(id);
+    let fn_info = match funcs.fn_env.get(id){
+        Some(f) => f,
+        None => {
+            note_error(&mut first, Diagnostic::error(ErrorCode::UndefinedFunction, &format!("cannot find function `{}`", id), span), context);
+            return Err(first.unwrap());
+        }
+    };
+    if fn_info.params.len() != params.len(){
+        let e = Diagnostic::error(ErrorCode::WrongArgumentCount, &format!("`{}` takes {} arguments but {} were supplied", id, fn_info.params.len(), params.len()), span);
+        note_error(&mut first, e, context);
+    }
     let mut i = 0;
-    for _param in &parameter_defs{
-        if parameter_defs[i].get_type_id() != new_fn_info.unwrap().params[i].get_type_id(){
-            return Err("mismatched parameter types");
+    //The type of the first argument given to a generic parameter of a builtin
+    let mut generic: Option<&Types> = None;
+    for _param in parameter_defs.iter().zip(&fn_info.params){
+        if fn_info.params[i].is_unknown(){
+            match generic{
+                Some(t) if !parameter_defs[i].compatible(t) => {
+                    let e = Diagnostic::error(ErrorCode::MismatchedTypes, &format!("mismatched types in argument {} of `{}`", i + 1, id), params[i].span)
+                        .with_types(t, &parameter_defs[i]);
+                    note_error(&mut first, e, context);
+                },
+                Some(_) => (),
+                None => generic = Some(&parameter_defs[i]),
+            }
+        }
+        else if !parameter_defs[i].coerces_to(&fn_info.params[i]){
+            let e = Diagnostic::error(ErrorCode::MismatchedTypes, &format!("mismatched types in argument {} of `{}`", i + 1, id), params[i].span)
+                .with_types(&fn_info.params[i], &parameter_defs[i]);
+            note_error(&mut first, e, context);
+        }
+        else if let Err(e) = check_literal_range(&params[i], &fn_info.params[i]){
+            note_error(&mut first, e, context);
         }
         i += 1;
     }
 
-    return Ok(new_fn_info.unwrap().ret.clone());
+    if let Some(e) = first{
+        return Err(e);
+    }
+    Ok(fn_info.ret.clone())
 }
 
-pub fn type_check_param_def(def: &String) -> Result<Types, &'static str> {
-    let ret_type = match def as &str{
-        ": i32" => Types::Number,
-        ": bool" => Types::Boolean,
-        ": &i32" => Types::Ref(Box::new(Types::Number)),
-        ": &bool" => Types::Ref(Box::new(Types::Boolean)),
-        ": &mut i32" => Types::MutRef(Box::new(Types::Number)),
-        ": &mut bool" => Types::MutRef(Box::new(Types::Boolean)),
-        _ => panic!("invalid param definition"),
-    };
-    return Ok(ret_type);
+//Translates a Type node of the syntax tree
+pub fn type_from_node(node: &Node) -> Result<Types, Diagnostic> {
+    match &node.kind{
+        NodeKind::Type(t) => type_from_expr(t, node.span),
+        _ => Err(Diagnostic::error(ErrorCode::InvalidProgram, "expected a type", node.span)),
+    }
 }
 
-pub fn check_param(node: &Node) -> Result<Types, &'static str>{
-    let ret = match node{
-        Node::ParamDef(_, s) => type_check_param_def(s),
-        _ => panic!("called on params"),
+pub fn type_from_expr(t: &TypeExpr, span: Span) -> Result<Types, Diagnostic> {
+    let ret_type = match t{
+        TypeExpr::Named(name) => match name.as_str(){
+            "bool" => Types::Boolean,
+            "f64" => Types::Float,
+            "char" => Types::Char,
+            "String" => Types::String,
+            _ => match IntType::from_name(name){
+                Some(i) => Types::Int(i),
+                None => return Err(Diagnostic::error(ErrorCode::UnknownType, &format!("cannot find type `{}`", name), span)),
+            },
+        },
+        TypeExpr::Unit => Types::UnitType,
+        //`str` is only used behind a reference
+        TypeExpr::Ref(t) if **t == TypeExpr::Named("str".to_string()) => Types::Str,
+        TypeExpr::Ref(t) => Types::Ref(Box::new(type_from_expr(t, span)?)),
+        TypeExpr::MutRef(t) => Types::MutRef(Box::new(type_from_expr(t, span)?)),
     };
-    ret
+    Ok(ret_type)
 }
 
+//References do not outlive the call that creates them, so apart from `&str`, which is passed by
+//value, a function cannot return one
+fn return_type(node: &Node) -> Result<Types, Diagnostic> {
+    match type_from_node(node)? {
+        Types::Ref(_) | Types::MutRef(_) => Err(Diagnostic::error(ErrorCode::InvalidBorrow, "functions cannot return references", node.span)
+            .with_note("return the value behind the reference instead")),
+        t => Ok(t),
+    }
+}
 
-pub fn type_check_op(node1: &Node, operation: &Opcode, node2: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, &'static str> {
+pub fn type_check_param_def(def: &Node) -> Result<Types, Diagnostic> {
+    type_from_node(def)
+}
+
+pub fn check_param(node: &Node) -> Result<Types, Diagnostic>{
+    match &node.kind{
+        NodeKind::ParamDef(_, t) => type_check_param_def(t),
+        _ => Err(Diagnostic::error(ErrorCode::InvalidProgram, "expected a parameter definition", node.span)),
+    }
+}
+
+
+#[allow(clippy::match_like_matches_macro, clippy::needless_return)]
+pub fn type_check_op(node1: &Node, operation: &Opcode, node2: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
     let compare = match operation {
         Opcode::Less => true,
         Opcode::LessOrEq => true,
@@ -327,33 +556,61 @@ pub fn type_check_op(node1: &Node, operation: &Opcode, node2: &Node, context: &m
         _ => false,
     };
 
-    let return_type = match operation {
-        Opcode::Add => Types::Number,
-        Opcode::Sub => Types::Number,
-        Opcode::Mul => Types::Number,
-        Opcode::Div => Types::Number,
-        Opcode::Mod => Types::Number,
-        _ => Types::Boolean,
+    let logic = match operation {
+        Opcode::And => true,
+        Opcode::Or => true,
+        _ => false,
     };
 
-    let left = type_check(node1, context, funcs);
-    let right = type_check(node2, context, funcs);
+    //Both operands are checked even if the first is invalid
+    let mut first = None;
+    let left = check_or_recover(node1, &mut first, context, funcs);
+    let right = check_or_recover(node2, &mut first, context, funcs);
+    if let Some(e) = first{
+        return Err(e);
+    }
+    if left.is_error() || right.is_error(){
+        return Ok(Types::Error);
+    }
+    //An operand that never has a value is compatible with every operation, which never finishes
+    if left == Types::Never || right == Types::Never{
+        return Ok(Types::Never);
+    }
+    let span = node1.span.to(node2.span);
+
+    //&& and || need booleans
+    if logic {
+        if left != Types::Boolean {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("invalid left operand for `{}`", operation), node1.span)
+                .with_types(&Types::Boolean, &left));
+        }
+        if right != Types::Boolean {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("invalid right operand for `{}`", operation), node2.span)
+                .with_types(&Types::Boolean, &right));
+        }
+        return Ok(Types::Boolean);
+    }
 
-    if left.is_err() || right.is_err(){
-        return Err("Error");
+    //Strings are concatenated with `+`, which always builds a new String
+    if let (Opcode::Add, true) = (operation, left.is_text()) {
+        if !right.is_text() {
+            return Err(Diagnostic::error(ErrorCode::MismatchedTypes, &format!("cannot concatenate `{}` to a string", right), node2.span)
+                .with_types(&Types::Str, &right));
+        }
+        return Ok(Types::String);
     }
 
-    //If we are not comparing the type returned from the operation needs to be equal to both inputs
+    //Arithmetic needs two numbers of the same type
     if !compare {
-        let x = left.clone();
-        if left.clone().unwrap().get_type_id() == right.unwrap().get_type_id() && //both operands are of same type
-        left.clone().unwrap().get_type_id() == return_type.get_type_id()          //as well as the returned type
-        &&(return_type.get_type_id() ==1 || return_type.get_type_id() == 2){      //number or boolean
-            return x;
+        if !left.is_numeric() {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("invalid left operand for `{}`", operation), node1.span)
+                .with_types(&Types::IntLiteral, &left));
         }
-        else {
-            return Err("Types not matched");
+        if !right.is_numeric() {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("invalid right operand for `{}`", operation), node2.span)
+                .with_types(&Types::IntLiteral, &right));
         }
+        return unify_numbers(node1, operation, node2, &left, &right);
     }
 
     //Otherwise we need to make sure both inputs are of equal type
@@ -365,249 +622,549 @@ pub fn type_check_op(node1: &Node, operation: &Opcode, node2: &Node, context: &m
             Opcode::GreaterorEq => true,
             _ => false,
         };
-        if left.clone().unwrap().get_type_id() != 1 && left.clone().unwrap().get_type_id() != 2{
-            return Err("invalid operands");
+        let unit_equality = left == Types::UnitType && !number_type;
+        //Chars are ordered by their code point, strings can only be compared for equality
+        if left == Types::Char && right == Types::Char {
+            return Ok(Types::Boolean);
+        }
+        if left.is_text() && right.is_text() && !number_type {
+            return Ok(Types::Boolean);
+        }
+        if left != Types::Boolean && !left.is_numeric() && !unit_equality{
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("`{}` cannot compare values of type `{}`", operation, left), node1.span));
         }
-        if number_type && (left.clone().unwrap().get_type_id() != 2 || right.clone().unwrap().get_type_id() != 2){
-            return Err("This operation requires both operands to be numbers");
+        if number_type && !left.is_numeric() {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("`{}` requires both operands to be numbers", operation), node1.span)
+                .with_types(&Types::IntLiteral, &left));
         }
-        if left.clone().unwrap().get_type_id() == right.unwrap().get_type_id() {
+        if number_type && !right.is_numeric() {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("`{}` requires both operands to be numbers", operation), node2.span)
+                .with_types(&Types::IntLiteral, &right));
+        }
+        if left.is_numeric() && right.is_numeric() {
+            unify_numbers(node1, operation, node2, &left, &right)?;
+            return Ok(Types::Boolean);
+        }
+        if left == right {
             return Ok(Types::Boolean);
         }
         else {
-            return Err("Types not matched");
+            return Err(Diagnostic::error(ErrorCode::MismatchedTypes, &format!("cannot compare `{}` with `{}`", left, right), span)
+                .with_types(&left, &right));
         }
     }
 }
 
-pub fn type_check_let(id: &String, mutable: &bool, typedef: &Option<String>, value: &Option<Box<Node>>, context: &mut Context, funcs: &mut FnContext) 
--> Result<Types, &'static str>{
+//Floats are never mixed with integers, not even with integer literals
+fn unify_numbers(node1: &Node, operation: &Opcode, node2: &Node, left: &Types, right: &Types) -> Result<Types, Diagnostic> {
+    if *left != Types::Float && *right != Types::Float {
+        return unify_operands(node1, operation, node2, left, right);
+    }
+    if left == right {
+        return Ok(Types::Float);
+    }
+    Err(Diagnostic::error(ErrorCode::MismatchedTypes, &format!("cannot apply `{}` to `{}` and `{}`", operation, left, right), node2.span)
+        .with_types(left, right)
+        .with_note("integers and floats need to be converted with `as`, write `1.0` for a float literal"))
+}
+
+//The type of an operation on two integers, which need to be of the same type unless one is a literal
+fn unify_operands(node1: &Node, operation: &Opcode, node2: &Node, left: &Types, right: &Types) -> Result<Types, Diagnostic> {
+    let t = match left.unify_int(right){
+        Some(t) => t,
+        None => return Err(Diagnostic::error(ErrorCode::MismatchedTypes, &format!("cannot apply `{}` to `{}` and `{}`", operation, left, right), node2.span)
+            .with_types(left, right)
+            .with_label(node1.span, &format!("this is of type `{}`", left))
+            .with_note("integers of different types need to be converted with `as`")),
+    };
+    check_literal_range(node1, &t)?;
+    check_literal_range(node2, &t)?;
+    Ok(t)
+}
+
+//An unsuffixed literal, possibly negated, used where a value of the given type is expected
+fn check_literal_range(node: &Node, t: &Types) -> Result<(), Diagnostic> {
+    let int_type = match t{
+        Types::Int(i) => i,
+        _ => return Ok(()),
+    };
+    let value = match &node.kind{
+        NodeKind::Number(v, None) => *v as i128,
+        NodeKind::UnaryOp(Opcode::UnarySub, n) => match &n.kind{
+            NodeKind::Number(v, None) => -(*v as i128),
+            _ => return Ok(()),
+        },
+        NodeKind::BlockValue(n) => return check_literal_range(n, t),
+        _ => return Ok(()),
+    };
+    literal_fits(value, int_type, node.span)
+}
+
+fn literal_fits(value: i128, t: &IntType, span: Span) -> Result<(), Diagnostic> {
+    if t.contains(value){
+        return Ok(());
+    }
+    Err(Diagnostic::error(ErrorCode::LiteralOutOfRange, &format!("literal out of range for `{}`", t), span)
+        .with_note(&format!("the literal `{}` does not fit into the type `{}` whose range is `{}..={}`", value, t, t.min(), t.max())))
+}
+
+pub fn type_check_let(id: &String, mutable: &bool, typedef: &Option<Box<Node>>, value: &Option<Box<Node>>, span: Span, context: &mut Context, funcs: &mut FnContext) 
+-> Result<Types, Diagnostic>{
     //A type can be specified
     let left = match typedef {
-        Some(s) => match s as &str {
-            ": i32" => Types::Number,
-            ": bool" => Types::Boolean,
-            ": &i32" => Types::Ref(Box::new(Types::Number)),
-            ": &bool" => Types::Ref(Box::new(Types::Boolean)),
-            _ => panic!("unrecognized type"),
+        Some(t) => match type_from_node(t) {
+            Ok(t) => t,
+            Err(e) => {
+                context.insert(id, &Types::Error, mutable);
+                return Err(e);
+            }
         },
         _ => Types::Unknown,
     };
 
-    //Check the type of the value assigned
-    let right = match value {
-        Some(n) => type_check(n, context, funcs),
-        _ => Err("no type"),
+    //if no value was assigned at all
+    let value = match value {
+        Some(n) => n,
+        None => {
+            context.insert(id, &left, mutable);
+            return Ok(Types::UnitType);
+        }
     };
 
-    //Check if the value assigned had a valid type
-    if right.is_ok(){
-        //If no type was specified on the left side
-        if left.get_type_id() == 0{
-            context.insert(id, &right.clone().unwrap(), mutable);
-            return right;
-        }
-        //If both sides match
-        else if left.get_type_id() == right.clone().unwrap().get_type_id() {
-            context.insert(id, &right.clone().unwrap(), mutable);
-            return right;
-        }
-        else{
-            return Err("unmatched types");
+    //Check the type of the value assigned, if it is invalid the variable still gets declared
+    //so that later uses of it are not reported as well
+    let right = match type_check(value, context, funcs){
+        Ok(t) => t,
+        Err(e) => {
+            let t = if left.is_unknown() {Types::Error} else {left};
+            context.insert(id, &t, mutable);
+            return Err(e);
         }
+    };
 
+    //Without a type the variable gets the type of the value, a literal defaulting to i32
+    if left.is_unknown() {
+        let t = right.defaulted();
+        context.insert(id, &t, mutable);
+        check_literal_range(value, &t)?;
+        return Ok(Types::UnitType);
     }
-
-    //if no value was assigned at all
-    if value.is_none(){
+    if left.compatible(&right) {
         context.insert(id, &left, mutable);
-        return Ok(left);
-    }
-
-    //Otherwise we know the expression was invalid
-    if right.is_err(){
-        return Err("Invalid Expression");
+        check_literal_range(value, &left)?;
+        return Ok(Types::UnitType);
     }
-
-    panic!("This line should be unreachable");
+    context.insert(id, &left, mutable);
+    Err(Diagnostic::error(ErrorCode::MismatchedTypes, "mismatched types", value.span)
+        .with_types(&left, &right)
+        .with_label(span, &format!("`{}` is declared with type `{}` here", id, left)))
 }
 
-pub fn type_check_assign(name: &String, value: &Node, context: &mut Context, funcs: &mut FnContext) 
--> Result<Types, &'static str>{
-    if context.get(name).is_none(){
-        return Err("tried assigning to non-existant variable");
-    }
-    let left = context.get(name).unwrap();
+#[allow(clippy::needless_return)]
+pub fn type_check_assign(name: &String, value: &Node, span: Span, context: &mut Context, funcs: &mut FnContext) 
+-> Result<Types, Diagnostic>{
+    let mut first = None;
+    let right = check_or_recover(value, &mut first, context, funcs);
+    let left = match context.get(name) {
+        Some(v) => v,
+        None => {
+            note_error(&mut first, Diagnostic::error(ErrorCode::UndefinedVariable, &format!("cannot find value `{}` in this scope", name), span), context);
+            return Err(first.unwrap());
+        }
+    };
     if !left.mutable {
-        return Err("Tried assigning a value to a nonmutable variable");
+        note_error(&mut first, Diagnostic::error(ErrorCode::AssignToImmutable, &format!("cannot assign twice to immutable variable `{}`", name), span)
+            .with_note(&format!("consider declaring it with `let mut {}`", name)), context);
     }
-
-    let right = type_check(value, context, funcs);
-    if right.is_err() {
-        return Err("Invalid expression");
+    if let Some(e) = first{
+        return Err(e);
     }
 
-    if left.t.get_type_id() != right.clone().unwrap().get_type_id() && left.t.get_type_id() != 0{
-        return Err("Mismatched types");
+    if !left.t.is_unknown() && !left.t.compatible(&right){
+        return Err(Diagnostic::error(ErrorCode::MismatchedTypes, "mismatched types", value.span)
+            .with_types(&left.t, &right));
     }
 
-    if left.t.get_type_id() == 0{
-        context.update(name, &right.clone().unwrap());
+    if left.t.is_unknown(){
+        context.update(name, &right.clone().defaulted());
     }
+    check_literal_range(value, &left.t)?;
 
-    return right;
+    //Like `let`, an assignment is a statement without a value
+    return Ok(Types::UnitType);
 }
 
-pub fn type_check_while(condition: &Node, instr: &Vec<Box<Node>>, context: &mut Context, funcs: &mut FnContext) 
--> Result<Types, &'static str>{
-    let condition_type = type_check(condition, context, funcs);
-    if condition_type.is_err(){
-        return Err("invalid expression");
+fn check_condition(condition: &Node, first: &mut Option<Diagnostic>, context: &mut Context, funcs: &mut FnContext){
+    let condition_type = check_or_recover(condition, first, context, funcs);
+    if !condition_type.compatible(&Types::Boolean) {
+        let e = Diagnostic::error(ErrorCode::MismatchedTypes, "condition is not a boolean", condition.span)
+            .with_types(&Types::Boolean, &condition_type);
+        note_error(first, e, context);
     }
+}
 
+//Checks a block in a new scope, recording its errors instead of stopping at them
+fn check_scope(instr: &[Box<Node>], first: &mut Option<Diagnostic>, context: &mut Context, funcs: &mut FnContext) -> Types{
     context.add_scope();
-    for n in instr{
-        let e = type_check(n, context, funcs);
-        if e.is_err() {
-            return e;
+    let t = match check_block(instr, context, funcs){
+        Ok(t) => t,
+        Err(e) => {
+            note_error(first, e, context);
+            Types::Error
         }
-    }
+    };
     context.remove_scope();
+    t
+}
 
-    if condition_type.unwrap().get_type_id() == 1 {
-        return Ok(Types::UnitType);
+#[allow(clippy::needless_return)]
+pub fn type_check_while(label: &Option<String>, condition: &Node, instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext) 
+-> Result<Types, Diagnostic>{
+    let mut first = None;
+    check_condition(condition, &mut first, context, funcs);
+    context.loops.push(LoopInfo{label: label.clone(), is_loop: false, break_type: None});
+    check_scope(instr, &mut first, context, funcs);
+    context.loops.pop();
+    if let Some(e) = first{
+        return Err(e);
     }
-    return Err("While condition did not evaluate to a boolean");
+    return Ok(Types::UnitType);
 }
 
-pub fn type_check_if(condition: &Node, instr: &Vec<Box<Node>>, context: &mut Context, funcs: &mut FnContext) 
-->  Result<Types, &'static str> {
-    let condition_type = type_check(condition, context, funcs);
-    if condition_type.is_err(){
-        return Err("invalid expression");
-    }
-    if condition_type.unwrap().get_type_id() == 1 {
-        if instr.len() == 0{
-            return Ok(Types::UnitType);
+//The type of a range is the integer type of its bounds
+pub fn type_check_range(start: &Node, end: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic>{
+    let mut first = None;
+    let mut types = Vec::new();
+    for bound in [start, end]{
+        let t = check_or_recover(bound, &mut first, context, funcs);
+        if !t.is_integer() && !t.is_error(){
+            let e = Diagnostic::error(ErrorCode::MismatchedTypes, "range bounds need to be numbers", bound.span)
+                .with_types(&Types::IntLiteral, &t);
+            note_error(&mut first, e, context);
         }
-        //If the final instruction does not have a semicolon the type will be determined by this instruction
-        let ret_type = match &*instr[instr.len()-1]{
-            Node::BlockValue(expr) => type_check(&expr, context, funcs),
-            _ => Ok(Types::UnitType),
+        types.push(t);
+    }
+    if let Some(e) = first{
+        return Err(e);
+    }
+    if types[0].is_error() || types[1].is_error(){
+        return Ok(Types::Error);
+    }
+    unify_operands(start, &Opcode::Sub, end, &types[0], &types[1]).map(|t| t.defaulted())
+}
 
-        };
+//The loop variable is an immutable number only visible in the body
+pub fn type_check_for(label: &Option<String>, var: &String, range: &Node, instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext) 
+-> Result<Types, Diagnostic>{
+    let mut first = None;
+    let t = match &range.kind{
+        NodeKind::Range(..) => check_or_recover(range, &mut first, context, funcs),
+        _ => return Err(Diagnostic::error(ErrorCode::InvalidProgram, "`for` loops can only iterate over ranges", range.span)),
+    };
+    context.add_scope();
+    context.insert(var, &t, &false);
+    context.loops.push(LoopInfo{label: label.clone(), is_loop: false, break_type: None});
+    check_scope(instr, &mut first, context, funcs);
+    context.loops.pop();
+    context.remove_scope();
+    if let Some(e) = first{
+        return Err(e);
+    }
+    Ok(Types::UnitType)
+}
 
-        context.add_scope();
-        for n in instr{
-            let e = type_check(n, context, funcs);
-            if e.is_err() {
-                return e;
+//The type of a loop is the type of the values it is exited with
+pub fn type_check_loop(label: &Option<String>, instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext) 
+-> Result<Types, Diagnostic>{
+    let mut first = None;
+    context.loops.push(LoopInfo{label: label.clone(), is_loop: true, break_type: None});
+    check_scope(instr, &mut first, context, funcs);
+    let info = context.loops.pop().unwrap();
+    if let Some(e) = first{
+        return Err(e);
+    }
+    //A loop without a `break` is only left by returning
+    match info.break_type{
+        Some(t) => Ok(t),
+        None => Ok(Types::Never),
+    }
+}
+
+//Finds the loop a break or continue refers to
+fn find_loop(label: &Option<String>, keyword: &str, span: Span, context: &Context) -> Result<usize, Diagnostic>{
+    let found = match label{
+        Some(l) => {
+            let position = context.loops.iter().rposition(|info| info.label.as_ref() == Some(l));
+            if position.is_none(){
+                return Err(Diagnostic::error(ErrorCode::UndeclaredLabel, &format!("use of undeclared label `'{}`", l), span));
             }
+            position
+        },
+        None => context.loops.len().checked_sub(1),
+    };
+    match found{
+        Some(i) => Ok(i),
+        None => Err(Diagnostic::error(ErrorCode::BreakOutsideLoop, &format!("`{}` outside of a loop", keyword), span)),
+    }
+}
+
+pub fn type_check_break(label: &Option<String>, value: &Option<Box<Node>>, span: Span, context: &mut Context, funcs: &mut FnContext) 
+-> Result<Types, Diagnostic>{
+    let t = match value{
+        Some(v) => type_check(v, context, funcs)?,
+        None => Types::UnitType,
+    };
+    let i = find_loop(label, "break", span, context)?;
+    let info = &mut context.loops[i];
+    if !info.is_loop{
+        if value.is_some(){
+            return Err(Diagnostic::error(ErrorCode::BreakWithValue, "`break` with a value can only be used in `loop`", span));
         }
-        context.remove_scope();
+        return Ok(Types::Never);
+    }
+    match &info.break_type{
+        None => info.break_type = Some(t),
+        Some(expected) => {
+            if !expected.compatible(&t){
+                let value_span = match value{
+                    Some(v) => v.span,
+                    None => span,
+                };
+                return Err(Diagnostic::error(ErrorCode::MismatchedTypes, "mismatched types in `break`", value_span)
+                    .with_types(expected, &t)
+                    .with_note("every `break` of a loop needs to give a value of the same type"));
+            }
+        },
+    };
+    Ok(Types::Never)
+}
+
+pub fn type_check_continue(label: &Option<String>, span: Span, context: &mut Context) -> Result<Types, Diagnostic>{
+    find_loop(label, "continue", span, context)?;
+    Ok(Types::Never)
+}
 
-        return ret_type;
+#[allow(clippy::needless_return)]
+pub fn type_check_if(condition: &Node, instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext) 
+->  Result<Types, Diagnostic> {
+    let mut first = None;
+    check_condition(condition, &mut first, context, funcs);
+    check_scope(instr, &mut first, context, funcs);
+    if let Some(e) = first{
+        return Err(e);
     }
-    return Err("If condition did not evaluate to a boolean"); 
+    //Without an else there is no value when the condition is false
+    return Ok(Types::UnitType);
 }
 
-pub fn type_check_if_else(condition: &Node, if_instr: &Vec<Box<Node>>, else_instr: &Vec<Box<Node>>, context: &mut Context, funcs: &mut FnContext)
-->  Result<Types, &'static str>{
-    let condition_type = type_check(condition, context, funcs);
-    if condition_type.is_err(){
-        return Err("invalid expression");
+pub fn type_check_block(instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic>{
+    let mut first = None;
+    let t = check_scope(instr, &mut first, context, funcs);
+    match first{
+        Some(e) => Err(e),
+        None => Ok(t),
     }
-    if condition_type.unwrap().get_type_id() == 1 {
-       let mut if_type: Types = Types::UnitType;
-       let mut else_type: Types = Types::UnitType;
+}
 
-       //Here the last instruction of each block is checked
-       if if_instr.len() > 0 {
-            if_type = match &*if_instr[if_instr.len()-1]{
-                Node::BlockValue(expr) => type_check(&expr, context, funcs).unwrap(),
-                _ => Types::UnitType,
+pub fn type_check_if_else(condition: &Node, if_instr: &[Box<Node>], else_instr: &[Box<Node>], context: &mut Context, funcs: &mut FnContext)
+->  Result<Types, Diagnostic>{
+    let mut first = None;
+    check_condition(condition, &mut first, context, funcs);
 
-            };
+    //The value of each branch is the value of the last instruction in it
+    let if_type = check_scope(if_instr, &mut first, context, funcs);
+    let else_type = check_scope(else_instr, &mut first, context, funcs);
+    if let Some(e) = first{
+        return Err(e);
+    }
 
-        }
+    //A branch that never finishes takes the type of the other one
+    if if_type == Types::Never{
+        return Ok(else_type);
+    }
+    if if_type.compatible(&else_type){
+        //A literal in one branch takes the integer type of the other
+        return Ok(if_type.unify_int(&else_type).unwrap_or(if_type));
+    }
 
-        if else_instr.len() > 0{
-            else_type = match &*else_instr[else_instr.len()-1]{
-                Node::BlockValue(expr) => type_check(&expr, context, funcs).unwrap(),
-                _ => Types::UnitType,
-            };
+    let if_span = match if_instr.last(){
+        Some(n) => n.span,
+        None => condition.span,
+    };
+    let else_span = match else_instr.last(){
+        Some(n) => n.span,
+        None => condition.span,
+    };
+    Err(Diagnostic::error(ErrorCode::MismatchedTypes, "`if` and `else` have incompatible types", else_span)
+        .with_types(&if_type, &else_type)
+        .with_label(if_span, "expected because of this"))
+}
 
-        }
+pub fn type_check_return(node: &Option<Box<Node>>, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic>{
+    let t = match node {
+        Some(b) => type_check(b, context, funcs)?,
+        None => Types::UnitType,
+    };
 
-        context.add_scope();        
-        for n in if_instr{
-            let e = type_check(n, context, funcs);
-            if e.is_err() {
-                return e;
-            }
-        }
-        context.remove_scope();
+    //A return can appear anywhere in the body, it always leaves the function
+    let expected = &context.ret_type;
+    if !expected.is_unknown() && !t.compatible(expected){
+        return Err(Diagnostic::error(ErrorCode::ReturnTypeMismatch, "mismatched return type", span)
+            .with_types(expected, &t));
+    }
+    if let Some(b) = node{
+        check_literal_range(b, expected)?;
+    }
+    Ok(Types::Never)
+}
 
-        context.add_scope();
-        for n in else_instr{
-            let e = type_check(n, context, funcs);
-            if e.is_err() {
-                return e;
-            }
-        }
-        context.remove_scope();
 
-        if if_type.get_type_id() == else_type.get_type_id(){
-            return Ok(if_type);
-        }
-        
-        else{
-            return Err("if and else statement return differing types");
+//Integers can be converted to other integer types, and booleans to integers
+pub fn type_check_cast(node: &Node, target: &Node, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
+    let mut first = None;
+    let from = check_or_recover(node, &mut first, context, funcs);
+    let to = match type_from_node(target){
+        Ok(t) => t,
+        Err(e) => {
+            note_error(&mut first, e, context);
+            Types::Error
         }
-       
+    };
+    if let Some(e) = first{
+        return Err(e);
+    }
+    let valid = matches!((&from, &to),
+        (Types::Int(_), Types::Int(_)) | (Types::IntLiteral, Types::Int(_)) | (Types::Boolean, Types::Int(_))
+        | (Types::Float, Types::Int(_)) | (Types::Int(_), Types::Float) | (Types::IntLiteral, Types::Float) | (Types::Float, Types::Float)
+        | (Types::Char, Types::Int(_)) | (Types::Int(IntType::U8), Types::Char) | (Types::Char, Types::Char)
+        | (Types::Error, _) | (_, Types::Error));
+    if !valid && to == Types::Char{
+        return Err(Diagnostic::error(ErrorCode::InvalidCast, &format!("only `u8` can be cast as `char`, not `{}`", from), span));
     }
-    return Err("If condition did not evaluate to a boolean");
+    if !valid{
+        return Err(Diagnostic::error(ErrorCode::InvalidCast, &format!("cannot cast `{}` as `{}`", from, to), span)
+            .with_note("only numbers and booleans can be cast, and only to number types"));
+    }
+    //The literal being cast is an `i32`, like in Rust
+    check_literal_range(node, &Types::Int(IntType::I32))?;
+    Ok(to)
 }
 
-pub fn type_check_return(node: &Option<Box<Node>>, context: &mut Context, funcs: &mut FnContext) -> Result<Types, &'static str>{
-    let ret = match node {
-        Some(b) => type_check(&b, context, funcs),
-        None => Ok(Types::UnitType),
-    };
+//`len()` is the only method, it gives the length of a string in bytes
+pub fn type_check_method_call(node: &Node, method: &String, args: &Vec<Box<Node>>, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
+    let mut first = None;
+    let t = check_or_recover(node, &mut first, context, funcs);
+    for arg in args{
+        check_or_recover(arg, &mut first, context, funcs);
+    }
+    if let Some(e) = first{
+        return Err(e);
+    }
+    if t.is_error(){
+        return Ok(Types::Error);
+    }
+    if method != "len" || !t.is_text(){
+        return Err(Diagnostic::error(ErrorCode::UnknownMethod, &format!("no method named `{}` found for `{}`", method, t), span)
+            .with_note("the only method is `len()` on strings"));
+    }
+    if !args.is_empty(){
+        return Err(Diagnostic::error(ErrorCode::WrongArgumentCount, &format!("`len` takes 0 arguments but {} were supplied", args.len()), span));
+    }
+    Ok(Types::Int(IntType::Usize))
+}
 
-    return ret;
+//Indexing a string gives the byte at a `usize` offset
+pub fn type_check_index(node: &Node, index: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
+    let mut first = None;
+    let t = check_or_recover(node, &mut first, context, funcs);
+    let i = check_or_recover(index, &mut first, context, funcs);
+    if let Some(e) = first{
+        return Err(e);
+    }
+    if !t.is_text() && !t.is_error(){
+        return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("cannot index into a value of type `{}`", t), node.span));
+    }
+    let usize_type = Types::Int(IntType::Usize);
+    if !i.compatible(&usize_type){
+        return Err(Diagnostic::error(ErrorCode::MismatchedTypes, "strings are indexed by `usize`", index.span)
+            .with_types(&usize_type, &i));
+    }
+    check_literal_range(index, &usize_type)?;
+    Ok(Types::Int(IntType::U8))
 }
 
+//The number of placeholders in the format string needs to match the number of arguments
+pub fn type_check_print(format: &Node, args: &Vec<Box<Node>>, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
+    let mut first = None;
+    let mut types = Vec::new();
+    for arg in args{
+        types.push(check_or_recover(arg, &mut first, context, funcs));
+    }
+    if let Some(e) = first{
+        return Err(e);
+    }
+    let pieces = match &format.kind{
+        NodeKind::Str(s) => format_pieces(s).map_err(|m| Diagnostic::error(ErrorCode::InvalidFormat, &format!("invalid format string: {}", m), format.span))?,
+        _ => return Err(Diagnostic::error(ErrorCode::InvalidFormat, "format argument must be a string literal", format.span)),
+    };
+    let placeholders: Vec<&FormatPiece> = pieces.iter().filter(|p| !matches!(p, FormatPiece::Text(_))).collect();
+    if placeholders.len() != args.len(){
+        return Err(Diagnostic::error(ErrorCode::InvalidFormat, &format!("{} placeholders in the format string, but {} arguments were supplied", placeholders.len(), args.len()), span)
+            .with_label(format.span, "the placeholders are in this string"));
+    }
+    for ((piece, t), arg) in placeholders.iter().zip(&types).zip(args){
+        if **piece == FormatPiece::Display && !t.is_displayable(){
+            return Err(Diagnostic::error(ErrorCode::InvalidFormat, &format!("`{}` cannot be formatted with `{{}}`", t), arg.span)
+                .with_note("use `{:?}` to print it for debugging"));
+        }
+    }
+    Ok(Types::UnitType)
+}
 
-pub fn type_check_unary_op(node: &Node, operation: &Opcode, context: &mut Context, funcs: &mut FnContext) -> Result<Types, &'static str> {
+#[allow(clippy::match_like_matches_macro, clippy::needless_return)]
+pub fn type_check_unary_op(node: &Node, operation: &Opcode, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
     let op_type = match operation {
-        Opcode::UnarySub => Types::Number,
+        Opcode::UnarySub => Types::IntLiteral,
         Opcode::Not => Types::Boolean,
         Opcode::Ref => Types::Unknown,
         Opcode::DeRef => Types::Unknown,
         Opcode::MutRef => Types::Unknown,
-        _=> panic!("Unrecognized unary op"),
+        _ => return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("`{}` is not a unary operator", operation), span)),
     };
-    //Unary -, expression needs to be a number
-    if op_type.get_type_id() == 2 {
-        let expr_type = type_check(node, context, funcs);
-        if expr_type.is_err(){
-            return Err("invalid expression");
+
+    //The smallest signed values can only be written as negated literals, like `-128i8`
+    if let (Opcode::UnarySub, NodeKind::Number(v, Some(t))) = (operation, &node.kind){
+        if t.signed(){
+            literal_fits(-(*v as i128), t, span)?;
+            return Ok(Types::Int(*t));
         }
-        if expr_type.unwrap().get_type_id() != 2 {
-            return Err("Unary - can only be used on numbers");
+    }
+
+    let expr_type = type_check(node, context, funcs)?;
+    if expr_type.is_error() || expr_type == Types::Never{
+        return Ok(expr_type);
+    }
+
+    //Unary -, expression needs to be a signed number
+    if op_type == Types::IntLiteral {
+        if !expr_type.is_numeric() {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, "unary `-` can only be used on numbers", span)
+                .with_types(&Types::IntLiteral, &expr_type));
+        }
+        if let Types::Int(t) = &expr_type{
+            if !t.signed(){
+                return Err(Diagnostic::error(ErrorCode::InvalidOperands, &format!("cannot apply unary operator `-` to type `{}`", t), span)
+                    .with_note("unsigned values cannot be negated"));
+            }
         }
-        return Ok(Types::Number);
+        return Ok(expr_type);
     }
 
     //Unary !, expression needs to be a boolean
-    if op_type.get_type_id() == 1 {
-        let expr_type = type_check(node, context, funcs);
-        if expr_type.is_err(){
-            return Err("invalid expression");
-        }
-        if expr_type.unwrap().get_type_id() != 1 {
-            return Err("Unary ! can only be used on booleans");
+    if op_type == Types::Boolean {
+        if expr_type != Types::Boolean {
+            return Err(Diagnostic::error(ErrorCode::InvalidOperands, "unary `!` can only be used on booleans", span)
+                .with_types(&Types::Boolean, &expr_type));
         }
         return Ok(Types::Boolean);
     }
@@ -619,104 +1176,128 @@ pub fn type_check_unary_op(node: &Node, operation: &Opcode, context: &mut Contex
             _ => false,
         };
 
-        let expr_type = type_check(node, context, funcs);
-        if expr_type.is_err(){
-            return Err("invalid expression");
-        }
-
         //If we are dereferencing we need to make sure the expression is a reference
         if !create_ref{
             
-            match expr_type.unwrap(){
+            match expr_type{
                 Types::MutRef(t) => return Ok(*t),
                 Types::Ref(t) => return Ok(*t),
-                _ => return Err("Tried dereferencing a non reference"),
+                t => return Err(Diagnostic::error(ErrorCode::InvalidDeref, &format!("type `{}` cannot be dereferenced", t), span)),
             }
         }
 
         let mutable = match operation {
             Opcode::MutRef => true,
-            Opcode::Ref => false,
-            _ => panic!("unreachable"),
-        };
-
-        let identifier = match node{
-            Node::ID(_s) => true,
             _ => false,
         };
 
-        if !identifier{
-            return Err("Tried creating a reference to a non ID");
-        }
+        let id_name = match &node.kind{
+            NodeKind::ID(s) => s,
+            _ => return Err(Diagnostic::error(ErrorCode::InvalidBorrow, "references can only be created to variables", span)),
+        };
 
         if mutable {
-            let id_name = match node{
-                Node::ID(s) => s,
-                _ => panic!("unreachable"),
+            let declared_mutable = match context.get(id_name){
+                Some(v) => v.mutable,
+                None => false,
             };
-            if !context.get(id_name).unwrap().mutable{
-                panic!("cannot borrow {:?} as mutable since it is not declared as mutable", id_name);
+            if !declared_mutable{
+                return Err(Diagnostic::error(ErrorCode::InvalidBorrow, &format!("cannot borrow `{}` as mutable, as it is not declared as mutable", id_name), span)
+                    .with_note(&format!("consider declaring it with `let mut {}`", id_name)));
             }
-            return Ok(Types::MutRef(Box::new(expr_type.unwrap())));
+            return Ok(Types::MutRef(Box::new(expr_type)));
         }
 
-        return Ok(Types::Ref(Box::new(expr_type.unwrap())));
+        return Ok(Types::Ref(Box::new(expr_type)));
         
     }
 }
 
-pub fn type_check_write_ref(op: &Opcode, id: &String, node: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, &'static str> {
+#[allow(clippy::match_like_matches_macro, clippy::needless_return)]
+pub fn type_check_write_ref(op: &Opcode, id: &String, node: &Node, span: Span, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
     let b = match op{
         Opcode::DeRef => true,
         _ => false,
     };
 
     if !b{
-        return Err("illegal operation for assignment");
+        return Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, "invalid left-hand side of assignment", span));
     }
 
-    let var = context.get(id);
-    if var.is_none(){
-        return Err("Unidentified variable name");
-    }
-    if var.clone().unwrap().t.get_type_id() != 5{
-        return Err("Syntax error");
-    }
-    let t = match var.unwrap().t{
+    let mut first = None;
+    let value_type = check_or_recover(node, &mut first, context, funcs);
+    let var = match context.get(id) {
+        Some(v) => v,
+        None => {
+            note_error(&mut first, Diagnostic::error(ErrorCode::UndefinedVariable, &format!("cannot find value `{}` in this scope", id), span), context);
+            return Err(first.unwrap());
+        }
+    };
+    let t = match var.t{
         Types::MutRef(t) => t,
-        _=> panic!("unreachable")
+        Types::Error => return match first {Some(e) => Err(e), None => Ok(Types::UnitType)},
+        t => {
+            note_error(&mut first, Diagnostic::error(ErrorCode::InvalidAssignTarget, &format!("cannot assign through `{}`, which is not a mutable reference", id), span)
+                .with_types(&Types::MutRef(Box::new(Types::Unknown)), &t), context);
+            return Err(first.unwrap());
+        }
     };
+    if let Some(e) = first{
+        return Err(e);
+    }
 
-    if t.get_type_id() != type_check(node, context, funcs).unwrap().get_type_id(){
-        return Err("mistmatched types");
-    }
-    return Ok(Types::Unknown);
-}
-
-
-
-pub fn type_check(node: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, &'static str> {
-    let ret = match node{
-        Node::Number(_n) => Ok(Types::Number),
-        Node::Boolean(_b) => Ok(Types::Boolean),
-        Node::Op(l, o, r) => type_check_op(l, o, r, context, funcs),
-        Node::ID(s) => Ok(context.get(s).unwrap().t),
-        Node::Declaration(name, b, typedef, value) 
-        => type_check_let(name, b, typedef, value, context, funcs),
-        Node::Assign(s, n) => type_check_assign(s, n, context, funcs),
-        Node::While(n, v) => type_check_while(n, v, context, funcs),
-        Node::IfStmt(n, v) => type_check_if(n, v, context, funcs),
-        Node::IfElse(n, v1, v2) => type_check_if_else(n, v1, v2, context, funcs),
-        Node::Return(o) => type_check_return(o, context, funcs),
-        Node::UnaryOp(op, exp) => type_check_unary_op(exp, op, context, funcs),
-        Node::FnDef(id, paramvec, ret, instr) => 
-        type_check_fn_def(id, paramvec, ret, instr, funcs),
-        Node::ParamDef(_id, s) => type_check_param_def(s),
-        Node::BlockValue(a) => type_check(a, context, funcs),
-        Node::Call(s, v) => type_check_call(s, v, context, funcs),
-        Node::WriteByRef(op, n, v) => type_check_write_ref(op, n, v, context, funcs),
-        Node::Program(v) => type_check_program(v),
-        _ => Err("unknown type"),
+    if !t.compatible(&value_type){
+        return Err(Diagnostic::error(ErrorCode::MismatchedTypes, "mismatched types", node.span)
+            .with_types(&t, &value_type));
+    }
+    check_literal_range(node, &t)?;
+    return Ok(Types::UnitType);
+}
+
+
+
+#[allow(clippy::needless_return)]
+pub fn type_check(node: &Node, context: &mut Context, funcs: &mut FnContext) -> Result<Types, Diagnostic> {
+    let ret = match &node.kind{
+        NodeKind::Number(_n, None) => Ok(Types::IntLiteral),
+        NodeKind::Float(_x) => Ok(Types::Float),
+        NodeKind::Str(_s) => Ok(Types::Str),
+        NodeKind::Char(_c) => Ok(Types::Char),
+        NodeKind::MethodCall(n, m, v) => type_check_method_call(n, m, v, node.span, context, funcs),
+        NodeKind::Index(n, i) => type_check_index(n, i, context, funcs),
+        NodeKind::Print(_, f, v) => type_check_print(f, v, node.span, context, funcs),
+        NodeKind::Number(n, Some(t)) => literal_fits(*n as i128, t, node.span).map(|_| Types::Int(*t)),
+        NodeKind::Cast(e, t) => type_check_cast(e, t, node.span, context, funcs),
+        NodeKind::Boolean(_b) => Ok(Types::Boolean),
+        NodeKind::Unit => Ok(Types::UnitType),
+        NodeKind::Op(l, o, r) => type_check_op(l, o, r, context, funcs),
+        NodeKind::ID(s) => match context.get(s){
+            Some(v) => Ok(v.t),
+            None => Err(Diagnostic::error(ErrorCode::UndefinedVariable, &format!("cannot find value `{}` in this scope", s), node.span)),
+        },
+        NodeKind::Declaration(name, b, typedef, value) 
+        => type_check_let(name, b, typedef, value, node.span, context, funcs),
+        NodeKind::Assign(s, n) => type_check_assign(s, n, node.span, context, funcs),
+        NodeKind::While(l, n, v) => type_check_while(l, n, v, context, funcs),
+        NodeKind::Loop(l, v) => type_check_loop(l, v, context, funcs),
+        NodeKind::For(l, var, range, v) => type_check_for(l, var, range, v, context, funcs),
+        NodeKind::Range(a, b, _, _) => type_check_range(a, b, context, funcs),
+        NodeKind::Break(l, v) => type_check_break(l, v, node.span, context, funcs),
+        NodeKind::Continue(l) => type_check_continue(l, node.span, context),
+        NodeKind::IfStmt(n, v) => type_check_if(n, v, context, funcs),
+        NodeKind::IfElse(n, v1, v2) => type_check_if_else(n, v1, v2, context, funcs),
+        NodeKind::Block(v) => type_check_block(v, context, funcs),
+        NodeKind::Return(o) => type_check_return(o, node.span, context, funcs),
+        NodeKind::UnaryOp(op, exp) => type_check_unary_op(exp, op, node.span, context, funcs),
+        NodeKind::FnDef(id, paramvec, ret, instr, _) => 
+        type_check_fn_def(id, paramvec, ret, instr, node.span, funcs).map_err(|e| first_error(e, context)),
+        NodeKind::ParamDef(_id, t) => type_check_param_def(t),
+        NodeKind::Type(t) => type_from_expr(t, node.span),
+        NodeKind::BlockValue(a) => type_check(a, context, funcs),
+        NodeKind::Call(s, v) => type_check_call(s, v, node.span, context, funcs),
+        NodeKind::WriteByRef(op, n, v) => type_check_write_ref(op, n, v, node.span, context, funcs),
+        NodeKind::Program(v) => type_check_program(v).map_err(|e| first_error(e, context)),
+        _ => Err(Diagnostic::error(ErrorCode::InvalidProgram, "unexpected syntax", node.span)),
         };
     return ret;
-}
\ No newline at end of file
+}